@@ -0,0 +1,66 @@
+// Compares reading a large binary STL through a buffer with mapping it, on
+// one thread and on all of them.
+//
+//     cargo bench --bench stl_read
+#![feature(old_io,old_path,os,test)]
+
+extern crate mesh;
+extern crate test;
+
+use std::old_io::{File,Reader};
+use std::old_path::Path;
+use std::os;
+use mesh::{Facet,Mesh,StlFile,Vector3D};
+use test::Bencher;
+
+// Enough facets that decoding, not setup, dominates.
+const GRID: usize = 400;
+
+// Writes a GRID x GRID sheet of triangles, 320,000 facets, once per run.
+fn fixture() -> Path {
+    let path = os::tmpdir().join("meshman-bench.stl");
+    if path.exists() {
+        return path;
+    }
+    let mut vertices = Vec::with_capacity((GRID + 1) * (GRID + 1));
+    for y in 0..GRID + 1 {
+        for x in 0..GRID + 1 {
+            vertices.push(Vector3D { x: x as f32, y: y as f32, z: ((x * y) % 7) as f32 });
+        }
+    }
+    let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+    let mut facets = Vec::with_capacity(2 * GRID * GRID);
+    for y in 0..GRID {
+        for x in 0..GRID {
+            let a = y * (GRID + 1) + x;
+            let b = a + GRID + 1;
+            facets.push(Facet { v1: a, v2: a + 1, v3: b, n: zero });
+            facets.push(Facet { v1: a + 1, v2: b + 1, v3: b, n: zero });
+        }
+    }
+    let mesh = Mesh::new_from_parts(vertices, facets);
+    let mut file = File::create(&path).unwrap();
+    StlFile::write_binary(&mesh, &mut file).unwrap();
+    path
+}
+
+#[bench]
+fn buffered(b: &mut Bencher) {
+    let path = fixture();
+    b.iter(|| {
+        let data = File::open(&path).read_to_end().unwrap();
+        StlFile::read_bytes(&data).unwrap().as_mesh()
+    });
+}
+
+#[bench]
+fn mapped_one_thread(b: &mut Bencher) {
+    let path = fixture();
+    b.iter(|| StlFile::read_mapped(&path, 1).unwrap());
+}
+
+#[bench]
+fn mapped_all_threads(b: &mut Bencher) {
+    let path = fixture();
+    b.iter(|| StlFile::read_mapped(&path, os::num_cpus()).unwrap());
+}
@@ -6,61 +6,144 @@ extern crate mesh;
 extern crate getopts;
 extern crate nalgebra;
 
-use std::old_io::BufferedReader;
+use std::old_io::{BufferedWriter,Reader,Writer};
+use std::old_io::stdio;
 use std::old_io::fs::File;
 use std::old_path::Path;
-use mesh::StlFile;
-use mesh::AmfFile;
+use mesh::FloatFormat;
+use mesh::PlyEncoding;
+use mesh::GltfFile;
 use mesh::POV;
-use mesh::Mesh;
+use mesh::{FormatRegistry,StlWriter,PlyWriter,AmfWriter};
+use mesh::{Mesh,MeshError,MeshResult};
 use mesh::Vector3D;
-use getopts::Options;
+use mesh::Unit;
+use mesh::RepairOptions;
+use mesh::{HoleOptions,Weighting};
+use getopts::{Matches,Options};
+use std::env;
 use std::os;
 use nalgebra::*;
 
+// Progress and errors go to stderr, keeping stdout free for mesh output.
+macro_rules! note {
+    ($($arg:tt)*) => { let _ = writeln!(&mut stdio::stderr(), $($arg)*); }
+}
+
 fn main() {
-    let args: Vec<String> = os::args();
-    let program = args[0].clone();
+    if let Err(e) = run() {
+        note!("meshman: {}", e);
+        env::set_exit_status(1);
+    }
+}
 
+// Output flags only switch a format on, so they never take the operation
+// that follows them as a file name; the -out options name the file.
+fn options() -> Options {
     let mut opts = Options::new();
-    opts.optopt("i", "input", "File name to process", "FILE");
-    opts.optflag("p", "povray", "Write the model to POV-Ray format file");
-    opts.optflag("a", "amf", "Write the model to AMF format file");
-    opts.optflag("s", "stl", "Write the model to STL format file");
-    opts.optflag("v", "view", "Print the model to the console");
+    opts.optopt("i", "input", "File name to process (default: stdin)", "FILE");
+    opts.optopt("", "from", "Input format, instead of guessing from the name or contents", "FORMAT");
+    opts.optopt("", "assume-units", "Unit of an input that does not declare one, e.g. STL: mm, cm, m, in, ft, um", "UNIT");
+    opts.optopt("o", "output", "Write the model to FILE, in the format its extension names (binary STL for -)", "FILE");
+    opts.optopt("", "to", "Output format for --output, or for stdout when no --output is given", "FORMAT");
+    opts.optflag("p", "povray", "Write the model to a POV-Ray scene and include file, named after the input");
+    opts.optopt("", "povray-out", "Write the model to a POV-Ray scene FILE and include file (- for stdout)", "FILE");
+    opts.optflag("a", "amf", "Write the model to AMF format file, named after the input");
+    opts.optopt("", "amf-out", "Write the model to AMF format FILE (- for stdout)", "FILE");
+    opts.optflag("", "amf-zip", "Compress AMF output into a zip archive");
+    opts.optflag("", "obj", "Write the model to Wavefront OBJ format file, named after the input");
+    opts.optopt("", "obj-out", "Write the model to Wavefront OBJ format FILE (- for stdout)", "FILE");
+    opts.optflag("", "off", "Write the model to OFF format file, named after the input");
+    opts.optopt("", "off-out", "Write the model to OFF format FILE (- for stdout)", "FILE");
+    opts.optflag("", "ply", "Write the model to PLY format file, named after the input");
+    opts.optopt("", "ply-out", "Write the model to PLY format FILE (- for stdout)", "FILE");
+    opts.optopt("", "ply-format", "PLY output encoding: ascii, binary_little_endian (default) or binary_big_endian", "FORMAT");
+    opts.optflag("", "3mf", "Write the model to 3MF package file, named after the input");
+    opts.optopt("", "3mf-out", "Write the model to 3MF package FILE (- for stdout)", "FILE");
+    opts.optflag("", "gltf", "Write the model to glTF (.gltf and .bin) files, named after the input");
+    opts.optopt("", "gltf-out", "Write the model to glTF FILE with a .bin beside it (- for stdout)", "FILE");
+    opts.optflag("", "glb", "Write the model to binary glTF (.glb) file, named after the input");
+    opts.optopt("", "glb-out", "Write the model to binary glTF (.glb) FILE (- for stdout)", "FILE");
+    opts.optflag("s", "stl", "Write the model to STL format file, named after the input");
+    opts.optopt("", "stl-out", "Write the model to STL format FILE (- for stdout)", "FILE");
+    opts.optopt("", "stl-format", "STL output encoding: binary (default) or ascii", "FORMAT");
+    opts.optopt("", "stl-header", "Text for the 80 byte header of binary STL output (default: the input's)", "TEXT");
+    opts.optopt("", "stl-digits", "Digits after the point in ASCII STL output (default: shortest)", "N");
+    opts.optflag("", "validate", "Check the model and print a JSON report, to stderr when the model goes to stdout; fails if the model is not valid");
+    opts.optopt("", "validate-out", "Like --validate, but write the report to FILE", "FILE");
+    opts.optflag("v", "view", "Print the model to stderr");
     opts.optflag("h", "help", "print this help menu");
+    opts
+}
+
+fn run() -> MeshResult<()> {
+    let args: Vec<String> = os::args();
+    let program = args[0].clone();
+    let opts = options();
 
     let matches = match opts.parse(args.tail()) {
         Ok(m) => { m }
-        Err(f) => { panic!(f.to_string()) }
+        Err(f) => { return Err(MeshError::InvalidArgument(f.to_string())); }
     };
 
     if matches.opt_present("h") {
         print_usage(program.as_slice(), &opts);
-        return;
+        return Ok(());
     };
 
-    let write_to_povray = matches.opt_present("p");
-    let write_to_amf = matches.opt_present("a");
-    let write_to_stl = matches.opt_present("s");
+    let amf_compressed = matches.opt_present("amf-zip");
     let write_to_console = matches.opt_present("v");
 
-    let input_file = match matches.opt_str("i") {
-        Some(x) => x,
-        None => { println!("No input file"); return; },
+    let stl_ascii = match matches.opt_str("stl-format") {
+        None => false,
+        Some(f) => match f.as_slice() {
+            "binary" => false,
+            "ascii" => true,
+            _ => { return Err(MeshError::InvalidArgument(format!("unknown STL format: {}", f))); }
+        },
     };
-
-    let file = File::open(&Path::new(input_file.clone()));
-    let meshfile = match StlFile::read(&mut BufferedReader::new(file)) {
-        Ok(f) => f,
-        Err(e) => { println!("STL read error: {}", e); return; }
+    let ply_encoding = match matches.opt_str("ply-format") {
+        None => PlyEncoding::BinaryLittleEndian,
+        Some(f) => match f.as_slice() {
+            "ascii" => PlyEncoding::Ascii,
+            "binary_little_endian" => PlyEncoding::BinaryLittleEndian,
+            "binary_big_endian" => PlyEncoding::BinaryBigEndian,
+            _ => { return Err(MeshError::InvalidArgument(format!("unknown PLY format: {}", f))); }
+        },
+    };
+    let stl_float_format = match matches.opt_str("stl-digits") {
+        None => FloatFormat::Shortest,
+        Some(d) => match d.parse::<usize>() {
+            Ok(n) => FloatFormat::Fixed(n),
+            Err(_) => { return Err(MeshError::InvalidArgument(format!("invalid digit count: {}", d))); }
+        },
     };
 
-    //meshfile.println_debug();
-    //println!("");
+    let input_file = matches.opt_str("i").unwrap_or("-".to_string());
 
-    let mesh = meshfile.as_mesh();
+    // The defaults, with the writers the options configure replacing theirs.
+    let solid_name = if input_file == "-" {
+        "meshman".to_string()
+    } else {
+        Path::new(input_file.clone()).filestem_str().unwrap_or("meshman").to_string()
+    };
+    let mut registry = FormatRegistry::with_defaults();
+    registry.register_writer(Box::new(StlWriter {
+        ascii: stl_ascii,
+        solid_name: solid_name,
+        float_format: stl_float_format,
+    }));
+    registry.register_writer(Box::new(PlyWriter { encoding: ply_encoding }));
+    registry.register_writer(Box::new(AmfWriter { compress: amf_compressed }));
+
+    let mut mesh = try!(read_mesh(&registry, &input_file, matches.opt_str("from")));
     //println!("Mesh: {:?}\n", &mesh);
+    if let Some(name) = matches.opt_str("assume-units") {
+        // Only fills in what the input did not declare.
+        if mesh.unit.is_none() {
+            mesh.unit = Some(try!(arg_to_unit(&name)));
+        }
+    }
 
     // Process free as commands
     let mut commands: Vec<Box<MeshOperation>> = Vec::new();
@@ -70,16 +153,20 @@ fn main() {
             None => break, // empty
             Some(x) => x.clone(),
         };
-        println!("Command: {}", command_name);
-        let vector = match iter.next() {
-            None => { println!("Every command requires a vector"); return; },
-            Some(y) => arg_to_vector(y.clone()),
+        note!("Command: {}", command_name);
+        let arg = match iter.next() {
+            None => { return Err(MeshError::InvalidArgument(format!("{} requires an argument", command_name))); },
+            Some(y) => y.clone(),
         };
         let command:Box<MeshOperation> = match command_name.as_slice() {
-            "rotate" => Box::new(RotateOperation { v: vector }),
-            "scale" => Box::new(ScaleOperation { v: vector }),
-            "translate" => Box::new(TranslateOperation { v: vector }),
-            _ => { println!("Unknown command: {}", command_name); return; }
+            "rotate" => Box::new(RotateOperation { v: try!(arg_to_vector(arg)) }),
+            "scale" => Box::new(ScaleOperation { v: try!(arg_to_vector(arg)) }),
+            "translate" => Box::new(TranslateOperation { v: try!(arg_to_vector(arg)) }),
+            "convert-units" => Box::new(ConvertUnitsOperation { to: try!(arg_to_unit(&arg)) }),
+            "weld" => Box::new(WeldOperation { epsilon: try!(arg_to_tolerance(&arg)) }),
+            "repair" => Box::new(RepairOperation { opts: try!(arg_to_repair_options(&arg)) }),
+            "fill-holes" => Box::new(FillHolesOperation { opts: try!(arg_to_hole_options(&arg)) }),
+            _ => { return Err(MeshError::InvalidArgument(format!("unknown command: {}", command_name))); }
         };
         commands.push( command );
     }
@@ -90,33 +177,144 @@ fn main() {
     loop {
         match it.next() {
             Some(command) => {
-                changed_mesh = command.apply(changed_mesh);
+                changed_mesh = try!(command.apply(changed_mesh));
             }
             None => { break }
         }
     }
 
-    if write_to_povray {
-        POV::write(&changed_mesh, &input_file);
+    if let Some(text) = matches.opt_str("stl-header") {
+        // Readers take a header starting with "solid" for ASCII STL.
+        if text.len() > 80 || text.starts_with("solid") {
+            return Err(MeshError::InvalidArgument(
+                format!("STL header must be at most 80 bytes and not start with 'solid': {}", text)));
+        }
+        changed_mesh.stl_header = text.into_bytes();
+    }
+
+    // --to alone sends the model to stdout.
+    let to = matches.opt_str("to");
+    let output = matches.opt_str("o").or(to.as_ref().map(|_| "-".to_string()));
+    // The model and the validation report cannot share stdout.
+    let outputs = ["stl", "amf", "obj", "off", "ply", "3mf", "glb", "gltf", "povray"];
+    let model_on_stdout = output.as_ref().map_or(false, |f| f.as_slice() == "-") ||
+        outputs.iter().any(|flag| matches.opt_str(&format!("{}-out", flag)).map_or(false, |f| f.as_slice() == "-"));
+    let report_file = matches.opt_str("validate-out");
+    if model_on_stdout && report_file.as_ref().map_or(false, |f| f.as_slice() == "-") {
+        return Err(MeshError::InvalidArgument("the validation report and the model cannot both go to stdout".to_string()));
+    }
+    if let Some(output_file) = output {
+        let format = match to {
+            Some(f) => f,
+            None if output_file == "-" => "stl".to_string(),
+            None => Path::new(output_file.clone()).extension_str().unwrap_or("").to_string(),
+        };
+        try!(write_output(&registry, &changed_mesh, &output_file, &format));
+    };
+    let formats = [("stl", "stl"), ("amf", "amf"), ("obj", "obj"), ("off", "off"),
+                   ("ply", "ply"), ("3mf", "3mf"), ("glb", "glb")];
+    for &(flag, ext) in formats.iter() {
+        if let Some(output_file) = output_target(&matches, flag, &input_file, ext) {
+            try!(write_output(&registry, &changed_mesh, &output_file, ext));
+        }
     };
-    if write_to_amf {
-        AmfFile::write(&changed_mesh, &input_file);
+    if let Some(output_file) = output_target(&matches, "gltf", &input_file, "gltf") {
+        // A separate buffer file makes no sense on stdout, so embed it there.
+        if output_file == "-" {
+            try!(write_output(&registry, &changed_mesh, &output_file, "gltf"));
+        } else {
+            try!(write_gltf(&changed_mesh, &Path::new(output_file.clone())));
+        }
     };
-    if write_to_stl {
-        let output_file = format!("new-{}", input_file);
-        let mut outfile = File::create(&Path::new(output_file.clone()));
-        println!("Writing Mesh to {}", output_file);
-        StlFile::write_binary(&changed_mesh, &mut outfile);
+    if let Some(output_file) = output_target(&matches, "povray", &input_file, "pov") {
+        // Only the mesh declaration goes to stdout; there is no file to include.
+        if output_file == "-" {
+            try!(write_output(&registry, &changed_mesh, "-", "pov"));
+        } else {
+            note!("Writing Mesh to {}", output_file);
+            try!(POV::write(&changed_mesh, &Path::new(output_file.clone()), &input_file));
+        }
     };
     if write_to_console {
         //meshfile.println_debug();
-        println!("Mesh: {:?}", &changed_mesh);
+        note!("Mesh: {:?}", &changed_mesh);
     };
+    if matches.opt_present("validate") || report_file.is_some() {
+        let report = changed_mesh.validate();
+        match report_file {
+            Some(ref f) if f.as_slice() != "-" => {
+                let mut out = try!(File::create(&Path::new(f.clone())));
+                try!(writeln!(&mut out, "{}", report.to_json()));
+            },
+            _ if model_on_stdout => try!(writeln!(&mut stdio::stderr(), "{}", report.to_json())),
+            _ => println!("{}", report.to_json()),
+        }
+        if !report.is_valid() {
+            return Err(MeshError::InvalidMesh("validation failed".to_string()));
+        }
+    };
+    Ok(())
+}
+
+// Where a format's output goes: the file its -out option names, or for the
+// bare flag the input's name with the format's extension. A derived name
+// that would overwrite the input gets a "-new" suffix instead.
+fn output_target(matches: &Matches, flag: &str, input_file: &str, ext: &str) -> Option<String> {
+    if let Some(f) = matches.opt_str(&format!("{}-out", flag)) {
+        return Some(f);
+    }
+    if !matches.opt_present(flag) {
+        return None;
+    }
+    let input_file = if input_file == "-" { "meshman" } else { input_file };
+    let input_path = Path::new(input_file);
+    let mut path = input_path.with_extension(ext);
+    if path == input_path {
+        let stem = input_path.filestem_str().unwrap_or("meshman").to_string();
+        path = input_path.with_filename(format!("{}-new.{}", stem, ext));
+    }
+    Some(path.display().to_string())
+}
+
+// Writes the mesh to the named file, or to stdout for "-", in the format of
+// the given name or extension.
+fn write_output(registry: &FormatRegistry, mesh: &Mesh, output_file: &str, format: &str) -> MeshResult<()> {
+    if output_file == "-" {
+        let mut out = BufferedWriter::new(stdio::stdout_raw());
+        try!(registry.write(mesh, &mut out, format));
+        return Ok(try!(out.flush()));
+    }
+    note!("Writing Mesh to {}", output_file);
+    let mut outfile = try!(File::create(&Path::new(output_file)));
+    registry.write(mesh, &mut outfile, format)
+}
+
+// Writes a .gltf document and, beside it, the .bin buffer it refers to.
+fn write_gltf(mesh: &Mesh, path: &Path) -> MeshResult<()> {
+    let bin_path = path.with_extension("bin");
+    let bin_uri = bin_path.filename_str().unwrap_or("model.bin").to_string();
+    note!("Writing Mesh to {}", path.display());
+    let mut outfile = try!(File::create(path));
+    let mut binfile = try!(File::create(&bin_path));
+    GltfFile::write_gltf(mesh, &mut outfile, &mut binfile, &bin_uri)
+}
+
+// Reads the named file, or stdin for "-". Unless the format is given, the
+// reader is picked by the file's extension or else by its contents.
+fn read_mesh(registry: &FormatRegistry, input_file: &str, from: Option<String>) -> MeshResult<Mesh> {
+    if input_file != "-" {
+        return registry.read_file(&Path::new(input_file), from.as_ref().map(|f| &f[..]));
+    }
+    let data = try!(stdio::stdin_raw().read_to_end());
+    match from {
+        Some(format) => registry.read_as(&data, &format),
+        None => registry.read(&data, None),
+    }
 }
 
 // Command pattern
 trait MeshOperation {
-    fn apply(&self, mesh: Mesh) -> Mesh;
+    fn apply(&self, mesh: Mesh) -> MeshResult<Mesh>;
 }
 
 pub struct RotateOperation {
@@ -131,8 +329,24 @@ pub struct TranslateOperation {
     v: Vector3D,
 }
 
+pub struct ConvertUnitsOperation {
+    to: Unit,
+}
+
+pub struct WeldOperation {
+    epsilon: f32,
+}
+
+pub struct RepairOperation {
+    opts: RepairOptions,
+}
+
+pub struct FillHolesOperation {
+    opts: HoleOptions,
+}
+
 impl MeshOperation for RotateOperation {
-    fn apply(&self, mesh: Mesh) -> Mesh {
+    fn apply(&self, mesh: Mesh) -> MeshResult<Mesh> {
         let rot = Rot3::new(Vec3::new(self.v.x, self.v.y, self.v.z));
 
         let v3s = mesh.vertices.iter()
@@ -141,13 +355,19 @@ impl MeshOperation for RotateOperation {
             .map(|v3| Vector3D {x: v3.x, y: v3.y, z: v3.z} )
             .collect();
 
+        let n3s = mesh.vertex_normals.iter()
+            .map(|v| Vec3::new(v.x, v.y, v.z) )
+            .map(|v3| rot.absolute_rotate(&v3))
+            .map(|v3| Vector3D {x: v3.x, y: v3.y, z: v3.z} )
+            .collect();
+
         //println!("{:?}", v3s);
-        return Mesh::new_from_parts(v3s, mesh.facets);
+        return Ok(Mesh { vertices: v3s, vertex_normals: n3s, .. mesh });
     }
 }
 
 impl MeshOperation for ScaleOperation {
-    fn apply(&self, mesh: Mesh) -> Mesh {
+    fn apply(&self, mesh: Mesh) -> MeshResult<Mesh> {
         let rot = Vec3::new(self.v.x, self.v.y, self.v.z);
 
         let v3s = mesh.vertices.iter()
@@ -157,12 +377,12 @@ impl MeshOperation for ScaleOperation {
         .collect();
 
         //println!("{:?}", v3s);
-        return Mesh::new_from_parts(v3s, mesh.facets);
+        return Ok(Mesh { vertices: v3s, .. mesh });
     }
 }
 
 impl MeshOperation for TranslateOperation {
-    fn apply(&self, mesh: Mesh) -> Mesh {
+    fn apply(&self, mesh: Mesh) -> MeshResult<Mesh> {
         let rot = Vec3::new(self.v.x, self.v.y, self.v.z);
 
         let v3s = mesh.vertices.iter()
@@ -172,24 +392,177 @@ impl MeshOperation for TranslateOperation {
         .collect();
 
         //println!("{:?}", v3s);
-        return Mesh::new_from_parts(v3s, mesh.facets);
+        return Ok(Mesh { vertices: v3s, .. mesh });
+    }
+}
+
+impl MeshOperation for ConvertUnitsOperation {
+    fn apply(&self, mut mesh: Mesh) -> MeshResult<Mesh> {
+        try!(mesh.convert_units(self.to));
+        Ok(mesh)
+    }
+}
+
+impl MeshOperation for WeldOperation {
+    fn apply(&self, mut mesh: Mesh) -> MeshResult<Mesh> {
+        let report = try!(mesh.weld(self.epsilon));
+        note!("Welded {} vertices, dropped {} collapsed facets", report.merged_vertices, report.dropped_facets);
+        Ok(mesh)
+    }
+}
+
+impl MeshOperation for RepairOperation {
+    fn apply(&self, mut mesh: Mesh) -> MeshResult<Mesh> {
+        let summary = try!(mesh.repair(&self.opts));
+        note!("Repaired: {}", summary);
+        Ok(mesh)
+    }
+}
+
+impl MeshOperation for FillHolesOperation {
+    fn apply(&self, mut mesh: Mesh) -> MeshResult<Mesh> {
+        let report = try!(mesh.fill_holes(&self.opts));
+        note!("Filled {} holes with {} facets and {} vertices, left {} open",
+              report.holes_filled, report.facets_added, report.vertices_added, report.holes_skipped);
+        Ok(mesh)
+    }
+}
+
+// "default", or a comma separated list of changes to the defaults: a step's
+// name turns it on, "no-" and the name turns it off, and weld, shells and
+// holes take a value, e.g. "weld=0.001,holes=50,no-outward".
+fn arg_to_repair_options(arg: &str) -> MeshResult<RepairOptions> {
+    let mut opts = RepairOptions::new();
+    if arg == "default" {
+        return Ok(opts);
+    }
+    for item in arg.split(',') {
+        let (name, value) = match item.find('=') {
+            Some(i) => (&item[..i], Some(&item[i + 1..])),
+            None => (item, None),
+        };
+        let count = |v: &str| v.parse::<usize>().map_err(|_| {
+            MeshError::InvalidArgument(format!("repair {} needs a whole number: {}", name, v))
+        });
+        match (name, value) {
+            ("weld", None) => opts.weld = Some(0.0),
+            ("weld", Some(v)) => opts.weld = Some(try!(arg_to_tolerance(v))),
+            ("no-weld", None) => opts.weld = None,
+            ("degenerate", None) => opts.remove_degenerate = true,
+            ("no-degenerate", None) => opts.remove_degenerate = false,
+            ("duplicates", None) => opts.remove_duplicates = true,
+            ("no-duplicates", None) => opts.remove_duplicates = false,
+            ("shells", Some(v)) => opts.min_shell_facets = try!(count(v)),
+            ("no-shells", None) => opts.min_shell_facets = 0,
+            ("winding", None) => opts.unify_winding = true,
+            ("no-winding", None) => opts.unify_winding = false,
+            ("outward", None) => opts.orient_outward = true,
+            ("no-outward", None) => opts.orient_outward = false,
+            ("holes", Some(v)) => opts.fill_holes = Some(try!(count(v))),
+            ("no-holes", None) => opts.fill_holes = None,
+            _ => { return Err(MeshError::InvalidArgument(format!("unknown repair option: {}", item))); }
+        }
     }
+    Ok(opts)
 }
 
-fn arg_to_vector(arg: String) -> Vector3D {
+// "default", or a comma separated list of changes to the defaults:
+// max=N for the largest hole to fill, area or dihedral for the weighting,
+// and refine, fair and their "no-" forms, e.g. "max=40,area,no-fair".
+fn arg_to_hole_options(arg: &str) -> MeshResult<HoleOptions> {
+    let mut opts = HoleOptions::new();
+    if arg == "default" {
+        return Ok(opts);
+    }
+    for item in arg.split(',') {
+        match item {
+            "area" => opts.weighting = Weighting::MinArea,
+            "dihedral" => opts.weighting = Weighting::MinDihedral,
+            "refine" => opts.refine = true,
+            "no-refine" => opts.refine = false,
+            "fair" => opts.fair = true,
+            "no-fair" => opts.fair = false,
+            _ if item.starts_with("max=") => {
+                opts.max_edges = try!(item[4..].parse::<usize>().map_err(|_| {
+                    MeshError::InvalidArgument(format!("fill-holes max needs a whole number: {}", &item[4..]))
+                }));
+            },
+            _ => { return Err(MeshError::InvalidArgument(format!("unknown fill-holes option: {}", item))); }
+        }
+    }
+    Ok(opts)
+}
+
+fn arg_to_tolerance(arg: &str) -> MeshResult<f32> {
+    match arg.parse::<f32>() {
+        Ok(e) if e >= 0.0 => Ok(e),
+        _ => Err(MeshError::InvalidArgument(format!("tolerance must be a number >= 0: {}", arg))),
+    }
+}
+
+fn arg_to_unit(arg: &str) -> MeshResult<Unit> {
+    match Unit::from_name(arg) {
+        Some(u) => Ok(u),
+        None => Err(MeshError::InvalidArgument(format!("unknown unit: {}", arg))),
+    }
+}
+
+fn arg_to_vector(arg: String) -> MeshResult<Vector3D> {
     let parts: Vec<f32> = arg.split(',').filter_map(|s| s.parse::<f32>().ok() ).collect();
     if parts.len() != 3 {
-        panic!("Vector must have three elements: {}", arg)
+        return Err(MeshError::InvalidArgument(format!("vector must have three elements: {}", arg)));
     };
-    Vector3D{x: parts[0], y: parts[1], z: parts[2]}
+    Ok(Vector3D{x: parts[0], y: parts[1], z: parts[2]})
 }
 
 #[test]
 fn converts_arg_to_vector() {
-    let result = arg_to_vector("1,2,3".to_string());
+    let result = arg_to_vector("1,2,3".to_string()).unwrap();
     assert_eq!(result.x, 1.0);
     assert_eq!(result.y, 2.0);
     assert_eq!(result.z, 3.0);
+    assert!(arg_to_vector("1,2".to_string()).is_err());
+}
+
+#[test]
+fn parses_repair_options() {
+    assert_eq!(arg_to_repair_options("default").unwrap(), RepairOptions::new());
+    let opts = arg_to_repair_options("weld=0.5,holes=40,no-outward,no-shells").unwrap();
+    assert_eq!(opts.weld, Some(0.5));
+    assert_eq!(opts.fill_holes, Some(40));
+    assert!(!opts.orient_outward && opts.unify_winding);
+    assert_eq!(opts.min_shell_facets, 0);
+    assert!(arg_to_repair_options("holes=many").is_err());
+    assert!(arg_to_repair_options("polish").is_err());
+}
+
+#[test]
+fn parses_hole_options() {
+    assert_eq!(arg_to_hole_options("default").unwrap(), HoleOptions::new());
+    let opts = arg_to_hole_options("max=40,area,no-fair").unwrap();
+    assert_eq!(opts, HoleOptions { max_edges: 40, weighting: Weighting::MinArea, fair: false, ..HoleOptions::new() });
+    assert!(arg_to_hole_options("max=").is_err());
+    assert!(arg_to_hole_options("smooth").is_err());
+}
+
+#[test]
+fn output_flags_leave_operations_alone() {
+    let args: Vec<String> = vec!["-s".to_string(), "rotate".to_string(), "0,0,1".to_string()];
+    let matches = options().parse(&args).unwrap();
+    assert!(matches.opt_present("s"));
+    assert_eq!(matches.free, vec!["rotate".to_string(), "0,0,1".to_string()]);
+    let args: Vec<String> = vec!["--stl-out".to_string(), "out.stl".to_string(), "rotate".to_string()];
+    let matches = options().parse(&args).unwrap();
+    assert_eq!(output_target(&matches, "stl", "in.stl", "stl"), Some("out.stl".to_string()));
+    assert_eq!(matches.free, vec!["rotate".to_string()]);
+}
+
+#[test]
+fn validate_leaves_operations_alone() {
+    let args: Vec<String> = vec!["--validate".to_string(), "repair".to_string(), "default".to_string()];
+    let matches = options().parse(&args).unwrap();
+    assert!(matches.opt_present("validate"));
+    assert_eq!(matches.free, vec!["repair".to_string(), "default".to_string()]);
 }
 
 #[test]
@@ -202,6 +575,10 @@ fn able_to_rotate() {
 }
 
 fn print_usage(program: &str, opts: &Options) {
-    let brief = format!("Usage: {} [options] [operation vector]", program);
+    let brief = format!("Usage: {} [options] [operation argument]...\n\n\
+                         Operations: rotate X,Y,Z  scale X,Y,Z  translate X,Y,Z  convert-units UNIT\n\
+                         \x20           weld EPSILON  repair OPTIONS|default  fill-holes OPTIONS|default\n\
+                         Output flags name the file after the input; their -out forms take a name (--stl-out out.stl), - meaning stdout.",
+                         program);
     print!("{}", opts.usage(brief.as_slice()));
 }
@@ -4,56 +4,603 @@
 #![deny(unused_must_use)]
 #![allow(deprecated)]
 
-use std::old_io::{File,IoResult,Reader,Writer};
+use std::old_io::{File,MemWriter,Reader,Writer};
 use std::old_path::Path;
-use mesh::Mesh;
+use std::str;
+use mesh::{Color,Facet,Mesh};
+use vector::Vector3D;
+use unit::Unit;
+use parse::parse_error;
+use xml::{Event,XmlReader,attr,escape};
+use zip;
+use zip::ZipEntry;
+use error::MeshResult;
 
 pub struct AmfFile;
 
-impl AmfFile {
+// Everything read from an AMF document, before it is flattened into a Mesh.
+pub struct AmfDocument {
+    pub unit: String,
+    pub metadata: Vec<(String, String)>,
+    pub materials: Vec<AmfMaterial>,
+    pub objects: Vec<AmfObject>,
+}
+
+pub struct AmfMaterial {
+    pub id: String,
+    pub metadata: Vec<(String, String)>,
+    pub color: Option<Color>,
+}
+
+// vertex_colors holds one entry per vertex.
+pub struct AmfObject {
+    pub id: String,
+    pub metadata: Vec<(String, String)>,
+    pub vertices: Vec<Vector3D>,
+    pub vertex_colors: Vec<Option<Color>>,
+    pub volumes: Vec<AmfVolume>,
+}
+
+// triangle_colors holds one entry per triangle.
+pub struct AmfVolume {
+    pub materialid: Option<String>,
+    pub metadata: Vec<(String, String)>,
+    pub color: Option<Color>,
+    pub triangles: Vec<Facet>,
+    pub triangle_colors: Vec<Option<Color>>,
+}
+
+impl AmfDocument {
+    // Wraps a mesh as a single object. Facets are split into one volume per
+    // distinct facet colour, each with its own coloured material.
+    pub fn from_mesh(m: &Mesh) -> AmfDocument {
+        let mut materials: Vec<AmfMaterial> = Vec::new();
+        let mut volumes: Vec<AmfVolume> = Vec::new();
+        for (i, f) in m.facets.iter().enumerate() {
+            let color = m.facet_colors.get(i).map(|c| *c);
+            let vi = match volumes.iter().position(|v| v.color == color) {
+                Some(vi) => vi,
+                None => {
+                    let materialid = color.map(|c| {
+                        let id = (materials.len() + 1).to_string();
+                        materials.push(AmfMaterial { id: id.clone(), metadata: Vec::new(), color: Some(c) });
+                        id
+                    });
+                    volumes.push(AmfVolume {
+                        materialid: materialid, metadata: Vec::new(), color: color,
+                        triangles: Vec::new(), triangle_colors: Vec::new(),
+                    });
+                    volumes.len() - 1
+                },
+            };
+            volumes[vi].triangles.push(*f);
+            volumes[vi].triangle_colors.push(None);
+        }
+        // AMF has no centimetres, so those meshes are written in millimetres.
+        let (unit, scale) = match m.unit {
+            Some(Unit::Centimeter) => ("millimeter", 10.0),
+            Some(Unit::Foot) => ("feet", 1.0),
+            Some(u) => (u.name(), 1.0),
+            None => ("millimeter", 1.0),
+        };
+        let object = AmfObject {
+            id: "1".to_string(),
+            metadata: Vec::new(),
+            vertices: m.vertices.iter().map(|v| v.scale(scale)).collect(),
+            vertex_colors: m.vertices.iter().enumerate()
+                            .map(|(i, _)| m.vertex_colors.get(i).map(|c| *c)).collect(),
+            volumes: volumes,
+        };
+        AmfDocument {
+            unit: unit.to_string(),
+            metadata: Vec::new(),
+            materials: materials,
+            objects: vec![object],
+        }
+    }
 
-    pub fn read(_: &mut Reader) -> IoResult<Mesh> {
-        panic!("not implemented");
+    pub fn material(&self, id: &str) -> Option<&AmfMaterial> {
+        self.materials.iter().find(|m| m.id == id)
     }
 
-    pub fn write(m: &Mesh, in_file_path: &str) -> IoResult<()> {
+    // Merges every volume of every object into a single mesh. A triangle
+    // takes its own colour, else its volume's, else its material's.
+    pub fn as_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new();
+        let mut vertex_colors = Vec::new();
+        let mut facet_colors = Vec::new();
+        for o in self.objects.iter() {
+            let base = mesh.vertices.len();
+            mesh.vertices.push_all(&o.vertices);
+            vertex_colors.push_all(&o.vertex_colors);
+            for vol in o.volumes.iter() {
+                let material_color = vol.materialid.as_ref()
+                    .and_then(|id| self.material(id))
+                    .and_then(|m| m.color);
+                for (t, c) in vol.triangles.iter().zip(vol.triangle_colors.iter()) {
+                    mesh.facets.push(Facet { v1: t.v1 + base, v2: t.v2 + base, v3: t.v3 + base, n: t.n });
+                    facet_colors.push((*c).or(vol.color).or(material_color));
+                }
+            }
+        }
+        if vertex_colors.iter().any(|c| c.is_some()) {
+            mesh.vertex_colors = vertex_colors.iter().map(|c| c.unwrap_or(Color::white())).collect();
+        }
+        if facet_colors.iter().any(|c| c.is_some()) {
+            mesh.facet_colors = facet_colors.iter().map(|c| c.unwrap_or(Color::white())).collect();
+        }
+        mesh.unit = Unit::from_name(&self.unit);
+        mesh.calculate_normals();
+        mesh
+    }
+}
+
+impl AmfFile {
 
-        let out_file_name = in_file_path.replace("stl", "amf");
-        let path = Path::new(out_file_name);
+    pub fn read(r: &mut Reader) -> MeshResult<Mesh> {
+        let doc = try!(AmfFile::read_document(r));
+        Ok(doc.as_mesh())
+    }
+
+    // Reads plain AMF XML, or the compressed form where the XML is the
+    // (first .amf) entry of a zip archive.
+    pub fn read_document(r: &mut Reader) -> MeshResult<AmfDocument> {
+        let data = try!(r.read_to_end());
+        if zip::is_zip(&data) {
+            let entries = try!(zip::read_entries(&data));
+            let entry = entries.iter().find(|e| e.name.to_lowercase().ends_with(".amf"))
+                                      .or(entries.first());
+            return match entry {
+                Some(e) => AmfFile::read_xml(&e.data),
+                None => Err(parse_error(1, "AMF archive is empty".to_string())),
+            };
+        }
+        AmfFile::read_xml(&data)
+    }
 
-        let mut out_file = match File::create(&path) {
-            Err(why) => return Err(why),
-            Ok(x) => x
+    fn read_xml(data: &[u8]) -> MeshResult<AmfDocument> {
+        let text = match str::from_utf8(data) {
+            Ok(t) => t,
+            Err(_) => return Err(parse_error(1, "AMF document is not valid UTF-8".to_string())),
         };
+        let mut xml = XmlReader::new(text);
+        let attrs = match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if *name == "amf" => attrs.clone(),
+            _ => return Err(parse_error(xml.line(), "expected <amf> root element".to_string())),
+        };
+        let mut doc = AmfDocument {
+            unit: attr(&attrs, "unit").unwrap_or("millimeter").to_string(),
+            metadata: Vec::new(),
+            materials: Vec::new(),
+            objects: Vec::new(),
+        };
+        loop {
+            match try!(xml.expect_event()) {
+                Event::Start(ref name, ref attrs) if *name == "object" => {
+                    let id = attr(attrs, "id").unwrap_or("").to_string();
+                    doc.objects.push(try!(read_object(&mut xml, id)));
+                },
+                Event::Start(ref name, ref attrs) if *name == "material" => {
+                    let id = attr(attrs, "id").unwrap_or("").to_string();
+                    doc.materials.push(try!(read_material(&mut xml, id)));
+                },
+                Event::Start(ref name, ref attrs) if *name == "metadata" => {
+                    let entry = try!(read_metadata(&mut xml, attrs));
+                    doc.metadata.push(entry);
+                },
+                Event::Start(..) => try!(xml.skip_element()),
+                Event::Text(_) => {},
+                Event::End(_) => break,
+            }
+        }
+        Ok(doc)
+    }
+
+    pub fn write(m: &Mesh, path: &Path) -> MeshResult<()> {
+        let mut out_file = try!(File::create(path));
+        AmfFile::write_xml(m, &mut out_file)
+    }
+
+    // Same as write, but stores the XML deflated inside a zip archive, as
+    // an entry named after the file.
+    pub fn write_compressed(m: &Mesh, path: &Path) -> MeshResult<()> {
+        let mut out_file = try!(File::create(path));
+        let name = format!("{}.amf", path.filestem_str().unwrap_or("model"));
+        AmfFile::write_zip(m, &mut out_file, &name)
+    }
+
+    // Writes a zip archive holding the AMF document as its single entry.
+    pub fn write_zip(m: &Mesh, w: &mut Writer, entry_name: &str) -> MeshResult<()> {
+        let mut xml = MemWriter::new();
+        try!(AmfFile::write_xml(m, &mut xml));
+        let entries = [ZipEntry { name: entry_name.to_string(), data: xml.into_inner() }];
+        zip::write_entries(w, &entries, true)
+    }
+
+    pub fn write_xml(m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        try!(m.check_indices());
+        AmfFile::write_document(&AmfDocument::from_mesh(m), w)
+    }
+
+    pub fn write_document(doc: &AmfDocument, w: &mut Writer) -> MeshResult<()> {
 
-        let amf_unit = "inch";
         let amf_version = "1.1";
-        let amf_oid = "1";
-        let amf_mid = "1";
-        //let amf_vname = "Test Volume";
-
-        try!(show(&mut out_file, format!("<amf unit='{}' version='{}'>", amf_unit, amf_version)));
-        try!(show(&mut out_file, format!("  <object id='{}'>", amf_oid)));
-        try!(show(&mut out_file, format!("    <vertices>")));
-        for v in m.vertices.iter() {
-            try!(show(&mut out_file,
-                format!("        <vertex><coordinates><x>{}</x><y>{}</y><z>{}</z></coordinates></vertex>", v.x, v.y, v.z)));
-        }
-        try!(show(&mut out_file, format!("    </vertices>")));
-        try!(show(&mut out_file, format!("    <volume materialid='{}'>", amf_mid)));
-        for t in m.facets.iter() {
-            try!(show(&mut out_file,
-                format!("        <triangle><v1>{}</v1><v2>{}</v2><v3>{}</v3></triangle>", t.v1, t.v2, t.v3)));
-        }
-        try!(show(&mut out_file, format!("    </volume>")));
-        try!(show(&mut out_file, format!("  </object>")));
-        try!(show(&mut out_file, format!("</amf>")));
+
+        try!(show(w, format!("<?xml version='1.0' encoding='UTF-8'?>")));
+        try!(show(w, format!("<amf unit='{}' version='{}'>", escape(&doc.unit), amf_version)));
+        try!(write_metadata(w, "  ", &doc.metadata));
+        for mat in doc.materials.iter() {
+            try!(show(w, format!("  <material id='{}'>", escape(&mat.id))));
+            try!(write_metadata(w, "    ", &mat.metadata));
+            if let Some(c) = mat.color {
+                try!(show(w, format!("    {}", color_xml(c))));
+            }
+            try!(show(w, format!("  </material>")));
+        }
+        for o in doc.objects.iter() {
+            try!(show(w, format!("  <object id='{}'>", escape(&o.id))));
+            try!(write_metadata(w, "    ", &o.metadata));
+            try!(show(w, format!("    <mesh>")));
+            try!(show(w, format!("      <vertices>")));
+            for (i, v) in o.vertices.iter().enumerate() {
+                let color = match o.vertex_colors.get(i) {
+                    Some(&Some(c)) => color_xml(c),
+                    _ => String::new(),
+                };
+                try!(show(w,
+                    format!("        <vertex><coordinates><x>{}</x><y>{}</y><z>{}</z></coordinates>{}</vertex>",
+                            v.x, v.y, v.z, color)));
+            }
+            try!(show(w, format!("      </vertices>")));
+            for vol in o.volumes.iter() {
+                match vol.materialid {
+                    Some(ref mid) => try!(show(w, format!("      <volume materialid='{}'>", escape(mid)))),
+                    None => try!(show(w, format!("      <volume>"))),
+                }
+                try!(write_metadata(w, "        ", &vol.metadata));
+                for (i, t) in vol.triangles.iter().enumerate() {
+                    let color = match vol.triangle_colors.get(i) {
+                        Some(&Some(c)) => color_xml(c),
+                        _ => String::new(),
+                    };
+                    try!(show(w,
+                        format!("        <triangle><v1>{}</v1><v2>{}</v2><v3>{}</v3>{}</triangle>",
+                                t.v1, t.v2, t.v3, color)));
+                }
+                try!(show(w, format!("      </volume>")));
+            }
+            try!(show(w, format!("    </mesh>")));
+            try!(show(w, format!("  </object>")));
+        }
+        try!(show(w, format!("</amf>")));
         Ok(())
     }
 }
 
-fn show(f: &mut File, s: String) -> IoResult<()> {
+fn write_metadata(w: &mut Writer, indent: &str, metadata: &[(String, String)]) -> MeshResult<()> {
+    for &(ref key, ref value) in metadata.iter() {
+        try!(show(w, format!("{}<metadata type='{}'>{}</metadata>", indent, escape(key), escape(value))));
+    }
+    Ok(())
+}
+
+fn color_xml(c: Color) -> String {
+    format!("<color><r>{}</r><g>{}</g><b>{}</b><a>{}</a></color>", c.r, c.g, c.b, c.a)
+}
+
+fn read_metadata(xml: &mut XmlReader, attrs: &[(String, String)]) -> MeshResult<(String, String)> {
+    let key = attr(attrs, "type").unwrap_or("").to_string();
+    let value = try!(xml.read_text("metadata"));
+    Ok((key, value.trim().to_string()))
+}
+
+fn read_material(xml: &mut XmlReader, id: String) -> MeshResult<AmfMaterial> {
+    let mut material = AmfMaterial { id: id, metadata: Vec::new(), color: None };
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if *name == "metadata" => {
+                let entry = try!(read_metadata(xml, attrs));
+                material.metadata.push(entry);
+            },
+            Event::Start(ref name, _) if *name == "color" => material.color = Some(try!(read_color(xml))),
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(material),
+        }
+    }
+}
+
+// <color><r>..</r><g>..</g><b>..</b><a>..</a></color>, alpha optional.
+fn read_color(xml: &mut XmlReader) -> MeshResult<Color> {
+    let mut c = [None, None, None, Some(1.0)];
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(name, _) => {
+                let i = match &name[..] {
+                    "r" => 0,
+                    "g" => 1,
+                    "b" => 2,
+                    "a" => 3,
+                    _ => { try!(xml.skip_element()); continue; },
+                };
+                c[i] = Some(try!(xml.read_f32(&name)));
+            },
+            Event::Text(_) => {},
+            Event::End(_) => break,
+        }
+    }
+    match (c[0], c[1], c[2], c[3]) {
+        (Some(r), Some(g), Some(b), Some(a)) => Ok(Color { r: r, g: g, b: b, a: a }),
+        _ => Err(parse_error(xml.line(), "<color> needs <r>, <g> and <b>".to_string())),
+    }
+}
+
+fn read_object(xml: &mut XmlReader, id: String) -> MeshResult<AmfObject> {
+    let mut object = AmfObject {
+        id: id, metadata: Vec::new(), vertices: Vec::new(), vertex_colors: Vec::new(), volumes: Vec::new(),
+    };
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if *name == "mesh" => try!(read_mesh(xml, &mut object)),
+            Event::Start(ref name, ref attrs) if *name == "metadata" => {
+                let entry = try!(read_metadata(xml, attrs));
+                object.metadata.push(entry);
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(object),
+        }
+    }
+}
+
+fn read_mesh(xml: &mut XmlReader, object: &mut AmfObject) -> MeshResult<()> {
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if *name == "vertices" => try!(read_vertices(xml, object)),
+            Event::Start(ref name, ref attrs) if *name == "volume" => {
+                let mid = attr(attrs, "materialid").map(|m| m.to_string());
+                let volume = try!(read_volume(xml, mid, object.vertices.len()));
+                object.volumes.push(volume);
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(()),
+        }
+    }
+}
+
+fn read_vertices(xml: &mut XmlReader, object: &mut AmfObject) -> MeshResult<()> {
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if *name == "vertex" => {
+                let (v, c) = try!(read_vertex(xml));
+                object.vertices.push(v);
+                object.vertex_colors.push(c);
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(()),
+        }
+    }
+}
+
+fn read_vertex(xml: &mut XmlReader) -> MeshResult<(Vector3D, Option<Color>)> {
+    let mut v = None;
+    let mut color = None;
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if *name == "coordinates" => v = Some(try!(read_coordinates(xml))),
+            Event::Start(ref name, _) if *name == "color" => color = Some(try!(read_color(xml))),
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => break,
+        }
+    }
+    match v {
+        Some(v) => Ok((v, color)),
+        None => Err(parse_error(xml.line(), "<vertex> without <coordinates>".to_string())),
+    }
+}
+
+fn read_coordinates(xml: &mut XmlReader) -> MeshResult<Vector3D> {
+    let mut c = [None, None, None];
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(name, _) => {
+                let i = match &name[..] {
+                    "x" => 0,
+                    "y" => 1,
+                    "z" => 2,
+                    _ => { try!(xml.skip_element()); continue; },
+                };
+                c[i] = Some(try!(xml.read_f32(&name)));
+            },
+            Event::Text(_) => {},
+            Event::End(_) => break,
+        }
+    }
+    match (c[0], c[1], c[2]) {
+        (Some(x), Some(y), Some(z)) => Ok(Vector3D { x: x, y: y, z: z }),
+        _ => Err(parse_error(xml.line(), "<coordinates> needs <x>, <y> and <z>".to_string())),
+    }
+}
+
+fn read_volume(xml: &mut XmlReader, materialid: Option<String>, vertex_count: usize) -> MeshResult<AmfVolume> {
+    let mut volume = AmfVolume {
+        materialid: materialid, metadata: Vec::new(), color: None,
+        triangles: Vec::new(), triangle_colors: Vec::new(),
+    };
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if *name == "triangle" => {
+                let (t, c) = try!(read_triangle(xml, vertex_count));
+                volume.triangles.push(t);
+                volume.triangle_colors.push(c);
+            },
+            Event::Start(ref name, ref attrs) if *name == "metadata" => {
+                let entry = try!(read_metadata(xml, attrs));
+                volume.metadata.push(entry);
+            },
+            Event::Start(ref name, _) if *name == "color" => volume.color = Some(try!(read_color(xml))),
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(volume),
+        }
+    }
+}
+
+fn read_triangle(xml: &mut XmlReader, vertex_count: usize) -> MeshResult<(Facet, Option<Color>)> {
+    let mut v = [None, None, None];
+    let mut color = None;
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if *name == "color" => color = Some(try!(read_color(xml))),
+            Event::Start(name, _) => {
+                let i = match &name[..] {
+                    "v1" => 0,
+                    "v2" => 1,
+                    "v3" => 2,
+                    _ => { try!(xml.skip_element()); continue; },
+                };
+                let idx = try!(xml.read_usize(&name));
+                if idx >= vertex_count {
+                    return Err(parse_error(xml.line(),
+                        format!("vertex index {} out of range ({} vertices)", idx, vertex_count)));
+                }
+                v[i] = Some(idx);
+            },
+            Event::Text(_) => {},
+            Event::End(_) => break,
+        }
+    }
+    match (v[0], v[1], v[2]) {
+        (Some(v1), Some(v2), Some(v3)) =>
+            Ok((Facet { v1: v1, v2: v2, v3: v3, n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } }, color)),
+        _ => Err(parse_error(xml.line(), "<triangle> needs <v1>, <v2> and <v3>".to_string())),
+    }
+}
+
+fn show(f: &mut Writer, s: String) -> MeshResult<()> {
     try!(f.write_str(&s));
     try!(f.write_str("\n"));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::Color;
+    use std::old_io::{BufReader,MemWriter};
+    use zip;
+    use zip::ZipEntry;
+    use parse::parse_error;
+
+    const TWO_OBJECTS: &'static str = "<?xml version='1.0' encoding='UTF-8'?>
+<amf unit='millimeter' version='1.1'>
+  <object id='1'>
+    <mesh>
+      <vertices>
+        <vertex><coordinates><x>0</x><y>0</y><z>0</z></coordinates></vertex>
+        <vertex><coordinates><x>1</x><y>0</y><z>0</z></coordinates></vertex>
+        <vertex><coordinates><x>0</x><y>1</y><z>0</z></coordinates></vertex>
+      </vertices>
+      <volume materialid='2'>
+        <triangle><v1>0</v1><v2>1</v2><v3>2</v3></triangle>
+      </volume>
+    </mesh>
+  </object>
+  <object id='2'>
+    <mesh>
+      <vertices>
+        <vertex><coordinates><x>5</x><y>5</y><z>5</z></coordinates></vertex>
+        <vertex><coordinates><x>6</x><y>5</y><z>5</z></coordinates></vertex>
+        <vertex><coordinates><x>5</x><y>6</y><z>5</z></coordinates></vertex>
+      </vertices>
+      <volume><triangle><v1>2</v1><v2>1</v2><v3>0</v3></triangle></volume>
+    </mesh>
+  </object>
+</amf>
+";
+
+    #[test]
+    fn reads_objects_and_volumes() {
+        let doc = AmfFile::read_document(&mut BufReader::new(TWO_OBJECTS.as_bytes())).unwrap();
+        assert_eq!(doc.unit, "millimeter");
+        assert_eq!(doc.objects.len(), 2);
+        assert_eq!(doc.objects[0].volumes[0].materialid, Some("2".to_string()));
+
+        let mesh = doc.as_mesh();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.facets.len(), 2);
+        assert_eq!(mesh.facets[1].v1, 5);
+        assert_eq!(mesh.vertices[5].y, 6.0);
+    }
+
+    #[test]
+    fn reads_materials_colors_and_metadata() {
+        let doc = TWO_OBJECTS
+            .replace("<amf unit='millimeter' version='1.1'>",
+                     "<amf unit='millimeter' version='1.1'>
+  <metadata type='name'>Dual &amp; colour</metadata>
+  <material id='2'><metadata type='name'>Red PLA</metadata>
+    <color><r>1</r><g>0</g><b>0</b></color></material>")
+            .replace("<z>0</z></coordinates></vertex>",
+                     "<z>0</z></coordinates><color><r>0</r><g>0</g><b>1</b><a>0.5</a></color></vertex>");
+        let doc = AmfFile::read_document(&mut BufReader::new(doc.as_bytes())).unwrap();
+        assert_eq!(doc.metadata, vec![("name".to_string(), "Dual & colour".to_string())]);
+        assert_eq!(doc.material("2").unwrap().color, Some(Color::rgb(1.0, 0.0, 0.0)));
+
+        let mesh = doc.as_mesh();
+        assert_eq!(mesh.facet_colors, vec![Color::rgb(1.0, 0.0, 0.0), Color::white()]);
+        assert_eq!(mesh.vertex_colors[0], Color { r: 0.0, g: 0.0, b: 1.0, a: 0.5 });
+        assert_eq!(mesh.vertex_colors[2], Color { r: 0.0, g: 0.0, b: 1.0, a: 0.5 });
+        assert_eq!(mesh.vertex_colors[3], Color::white());
+    }
+
+    #[test]
+    fn written_documents_read_back() {
+        let mut mesh = AmfFile::read(&mut BufReader::new(TWO_OBJECTS.as_bytes())).unwrap();
+        mesh.unit = None;
+
+        let mut w = MemWriter::new();
+        AmfFile::write_xml(&mesh, &mut w).unwrap();
+        let data = w.into_inner();
+        let text = String::from_utf8(data.clone()).unwrap();
+        assert!(text.contains("<object id='1'>\n    <mesh>\n      <vertices>"));
+
+        let read = AmfFile::read(&mut BufReader::new(&data)).unwrap();
+        assert_eq!(read.vertices, mesh.vertices);
+        assert_eq!(read.facets, mesh.facets);
+    }
+
+    #[test]
+    fn colors_round_trip() {
+        let mut mesh = AmfFile::read(&mut BufReader::new(TWO_OBJECTS.as_bytes())).unwrap();
+        mesh.facet_colors = vec![Color::rgb(0.0, 1.0, 0.0), Color::rgb(0.0, 0.0, 1.0)];
+
+        let mut w = MemWriter::new();
+        AmfFile::write_xml(&mesh, &mut w).unwrap();
+        let data = w.into_inner();
+        let doc = AmfFile::read_document(&mut BufReader::new(&data)).unwrap();
+        assert_eq!(doc.materials.len(), 2);
+        assert_eq!(doc.objects[0].volumes.len(), 2);
+        assert_eq!(doc.as_mesh(), mesh);
+    }
+
+    #[test]
+    fn reads_zipped_amf() {
+        let entries = [ZipEntry { name: "part.amf".to_string(), data: TWO_OBJECTS.as_bytes().to_vec() }];
+        let mut w = MemWriter::new();
+        zip::write_entries(&mut w, &entries, true).unwrap();
+        let data = w.into_inner();
+        let mesh = AmfFile::read(&mut BufReader::new(&data)).unwrap();
+        assert_eq!(mesh.facets.len(), 2);
+    }
+
+    #[test]
+    fn bad_index_is_a_parse_error() {
+        let doc = TWO_OBJECTS.replace("<v3>2</v3>", "<v3>7</v3>");
+        let err = AmfFile::read(&mut BufReader::new(doc.as_bytes())).err().unwrap();
+        assert_eq!(err, parse_error(11, "vertex index 7 out of range (3 vertices)".to_string()));
+    }
+}
@@ -0,0 +1,93 @@
+// The error type shared by every reader, writer and operation in the crate.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::error::{Error,FromError};
+use std::fmt;
+use std::old_io::IoError;
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum MeshError {
+    // Reading or writing the underlying file or stream failed.
+    Io(IoError),
+    // Malformed input. Line 0 means the data has no lines, as in binary
+    // formats and archives.
+    Parse { line: usize, msg: String },
+    // A facet refers to a vertex the mesh does not have.
+    InvalidIndex { facet: usize, index: usize, vertices: usize },
+    // No reader or writer handles the requested format.
+    UnsupportedFormat(String),
+    // A caller supplied a value that makes no sense, such as a vector
+    // without three components.
+    InvalidArgument(String),
+    // The mesh itself is unusable as it is, such as one that failed
+    // validation.
+    InvalidMesh(String),
+}
+
+pub type MeshResult<T> = Result<T, MeshError>;
+
+impl FromError<IoError> for MeshError {
+    fn from_error(err: IoError) -> MeshError {
+        MeshError::Io(err)
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MeshError::Io(ref e) => write!(f, "{}", e),
+            MeshError::Parse { line: 0, ref msg } => write!(f, "parse error: {}", msg),
+            MeshError::Parse { line, ref msg } => write!(f, "parse error: line {}: {}", line, msg),
+            MeshError::InvalidIndex { facet, index, vertices } =>
+                write!(f, "facet {} uses vertex {}, but there are only {} vertices", facet, index, vertices),
+            MeshError::UnsupportedFormat(ref msg) => write!(f, "unsupported format: {}", msg),
+            MeshError::InvalidArgument(ref msg) => write!(f, "invalid argument: {}", msg),
+            MeshError::InvalidMesh(ref msg) => write!(f, "invalid mesh: {}", msg),
+        }
+    }
+}
+
+impl Error for MeshError {
+    fn description(&self) -> &str {
+        match *self {
+            MeshError::Io(ref e) => e.description(),
+            MeshError::Parse { .. } => "parse error",
+            MeshError::InvalidIndex { .. } => "invalid vertex index",
+            MeshError::UnsupportedFormat(_) => "unsupported format",
+            MeshError::InvalidArgument(_) => "invalid argument",
+            MeshError::InvalidMesh(_) => "invalid mesh",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            MeshError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::FromError;
+    use std::old_io::{IoError,IoErrorKind};
+
+    #[test]
+    fn messages_carry_the_location() {
+        let err = MeshError::Parse { line: 4, msg: "invalid number 'zero'".to_string() };
+        assert_eq!(err.to_string(), "parse error: line 4: invalid number 'zero'");
+        let err = MeshError::Parse { line: 0, msg: "archive is truncated".to_string() };
+        assert_eq!(err.to_string(), "parse error: archive is truncated");
+        let err = MeshError::InvalidIndex { facet: 2, index: 9, vertices: 3 };
+        assert_eq!(err.to_string(), "facet 2 uses vertex 9, but there are only 3 vertices");
+    }
+
+    #[test]
+    fn io_errors_convert() {
+        let io = IoError { kind: IoErrorKind::EndOfFile, desc: "end of file", detail: None };
+        let err: MeshError = FromError::from_error(io.clone());
+        assert_eq!(err, MeshError::Io(io));
+    }
+}
@@ -0,0 +1,474 @@
+// Format-independent reading and writing, and a registry to pick the right
+// reader or writer by file extension or by sniffing the data.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::cmp;
+use std::os;
+use std::old_io::{BufReader,File,Reader,Writer};
+use std::old_path::Path;
+use mesh::Mesh;
+use stl;
+use stl::{StlFile,FloatFormat};
+use amf::AmfFile;
+use obj::ObjFile;
+use off::OffFile;
+use ply::{PlyFile,PlyEncoding};
+use threemf::ThreeMfFile;
+use gltf::GltfFile;
+use pov::POV;
+use zip;
+use error::{MeshError,MeshResult};
+
+const STL_EXTENSIONS: &'static [&'static str] = &["stl"];
+const AMF_EXTENSIONS: &'static [&'static str] = &["amf"];
+const OBJ_EXTENSIONS: &'static [&'static str] = &["obj"];
+const OFF_EXTENSIONS: &'static [&'static str] = &["off"];
+const PLY_EXTENSIONS: &'static [&'static str] = &["ply"];
+const THREEMF_EXTENSIONS: &'static [&'static str] = &["3mf"];
+const GLTF_EXTENSIONS: &'static [&'static str] = &["gltf"];
+const GLB_EXTENSIONS: &'static [&'static str] = &["glb"];
+const POV_EXTENSIONS: &'static [&'static str] = &["inc"];
+
+pub trait MeshReader {
+    // Short name used to pick the format explicitly, e.g. "stl".
+    fn name(&self) -> &'static str;
+
+    // Lower case file extensions, without the dot.
+    fn extensions(&self) -> &'static [&'static str];
+
+    // Whether the (complete) file contents look like this format.
+    fn sniff(&self, data: &[u8]) -> bool;
+
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh>;
+
+    // Reads a file on disk. Readers that do better with the whole file at
+    // hand than with a stream override this.
+    fn read_file(&self, path: &Path) -> MeshResult<Mesh> {
+        let mut f = try!(File::open(path));
+        self.read(&mut f)
+    }
+}
+
+pub trait MeshWriter {
+    fn name(&self) -> &'static str;
+
+    fn extensions(&self) -> &'static [&'static str];
+
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()>;
+}
+
+pub struct StlReader;
+
+impl MeshReader for StlReader {
+    fn name(&self) -> &'static str { "stl" }
+    fn extensions(&self) -> &'static [&'static str] { STL_EXTENSIONS }
+    fn sniff(&self, data: &[u8]) -> bool { stl::sniff(data) }
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh> {
+        Ok(try!(StlFile::read(r)).as_mesh())
+    }
+    // STL files can be huge, so they get mapped and decoded in place.
+    fn read_file(&self, path: &Path) -> MeshResult<Mesh> {
+        StlFile::read_mapped(path, os::num_cpus())
+    }
+}
+
+// Binary by default; `ascii` selects the text form.
+pub struct StlWriter {
+    pub ascii: bool,
+    pub solid_name: String,
+    pub float_format: FloatFormat,
+}
+
+impl MeshWriter for StlWriter {
+    fn name(&self) -> &'static str { "stl" }
+    fn extensions(&self) -> &'static [&'static str] { STL_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        if self.ascii {
+            StlFile::write_ascii(m, w, &self.solid_name, self.float_format)
+        } else {
+            StlFile::write_binary(m, w)
+        }
+    }
+}
+
+pub struct AmfReader;
+
+impl MeshReader for AmfReader {
+    fn name(&self) -> &'static str { "amf" }
+    fn extensions(&self) -> &'static [&'static str] { AMF_EXTENSIONS }
+    fn sniff(&self, data: &[u8]) -> bool {
+        if zip::is_zip(data) {
+            return match zip::names(data) {
+                Ok(names) => names.iter().any(|n| n.to_lowercase().ends_with(".amf")),
+                Err(_) => false,
+            };
+        }
+        contains(&data[..cmp::min(data.len(), 1024)], b"<amf")
+    }
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh> {
+        AmfFile::read(r)
+    }
+}
+
+pub struct AmfWriter {
+    pub compress: bool,
+}
+
+impl MeshWriter for AmfWriter {
+    fn name(&self) -> &'static str { "amf" }
+    fn extensions(&self) -> &'static [&'static str] { AMF_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        if self.compress {
+            AmfFile::write_zip(m, w, "model.amf")
+        } else {
+            AmfFile::write_xml(m, w)
+        }
+    }
+}
+
+pub struct ObjFormat;
+
+impl MeshReader for ObjFormat {
+    fn name(&self) -> &'static str { "obj" }
+    fn extensions(&self) -> &'static [&'static str] { OBJ_EXTENSIONS }
+    fn sniff(&self, data: &[u8]) -> bool {
+        // The first statement of a typical OBJ file.
+        let head = String::from_utf8_lossy(&data[..cmp::min(data.len(), 1024)]).into_owned();
+        head.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with("#")).next()
+            .map_or(false, |l| ["v ", "vn ", "vt ", "o ", "g ", "mtllib "].iter().any(|k| l.starts_with(*k)))
+    }
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh> {
+        try!(ObjFile::read(r)).as_mesh()
+    }
+}
+
+impl MeshWriter for ObjFormat {
+    fn name(&self) -> &'static str { "obj" }
+    fn extensions(&self) -> &'static [&'static str] { OBJ_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        try!(m.check_indices());
+        ObjFile::from_mesh(m, "mesh").write(w)
+    }
+}
+
+pub struct OffFormat;
+
+impl MeshReader for OffFormat {
+    fn name(&self) -> &'static str { "off" }
+    fn extensions(&self) -> &'static [&'static str] { OFF_EXTENSIONS }
+    fn sniff(&self, data: &[u8]) -> bool {
+        let start = data.iter().position(|b| !(*b as char).is_whitespace()).unwrap_or(data.len());
+        data[start..].starts_with(b"OFF") || data[start..].starts_with(b"COFF")
+    }
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh> {
+        OffFile::read(r)
+    }
+}
+
+impl MeshWriter for OffFormat {
+    fn name(&self) -> &'static str { "off" }
+    fn extensions(&self) -> &'static [&'static str] { OFF_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        OffFile::write(m, w)
+    }
+}
+
+pub struct PlyReader;
+
+impl MeshReader for PlyReader {
+    fn name(&self) -> &'static str { "ply" }
+    fn extensions(&self) -> &'static [&'static str] { PLY_EXTENSIONS }
+    fn sniff(&self, data: &[u8]) -> bool {
+        data.starts_with(b"ply\n") || data.starts_with(b"ply\r\n")
+    }
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh> {
+        try!(PlyFile::read(r)).as_mesh()
+    }
+}
+
+pub struct PlyWriter {
+    pub encoding: PlyEncoding,
+}
+
+impl MeshWriter for PlyWriter {
+    fn name(&self) -> &'static str { "ply" }
+    fn extensions(&self) -> &'static [&'static str] { PLY_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        PlyFile::write(m, w, self.encoding)
+    }
+}
+
+pub struct ThreeMfFormat;
+
+impl MeshReader for ThreeMfFormat {
+    fn name(&self) -> &'static str { "3mf" }
+    fn extensions(&self) -> &'static [&'static str] { THREEMF_EXTENSIONS }
+    fn sniff(&self, data: &[u8]) -> bool {
+        zip::is_zip(data) && match zip::names(data) {
+            Ok(names) => names.iter().any(|n| n.ends_with(".model")),
+            Err(_) => false,
+        }
+    }
+    fn read(&self, r: &mut Reader) -> MeshResult<Mesh> {
+        try!(ThreeMfFile::read(r)).as_mesh()
+    }
+}
+
+impl MeshWriter for ThreeMfFormat {
+    fn name(&self) -> &'static str { "3mf" }
+    fn extensions(&self) -> &'static [&'static str] { THREEMF_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        try!(m.check_indices());
+        try!(ThreeMfFile::from_mesh(m)).write(w)
+    }
+}
+
+// A single .gltf, with the buffer embedded so that one sink suffices.
+pub struct GltfWriter;
+
+impl MeshWriter for GltfWriter {
+    fn name(&self) -> &'static str { "gltf" }
+    fn extensions(&self) -> &'static [&'static str] { GLTF_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        GltfFile::write_embedded(m, w)
+    }
+}
+
+pub struct GlbWriter;
+
+impl MeshWriter for GlbWriter {
+    fn name(&self) -> &'static str { "glb" }
+    fn extensions(&self) -> &'static [&'static str] { GLB_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        GltfFile::write_glb(m, w)
+    }
+}
+
+// The POV-Ray mesh declaration, for including from a scene file.
+pub struct PovWriter;
+
+impl MeshWriter for PovWriter {
+    fn name(&self) -> &'static str { "pov" }
+    fn extensions(&self) -> &'static [&'static str] { POV_EXTENSIONS }
+    fn write(&self, m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        POV::write_mesh(m, w, "meshman", "m_model")
+    }
+}
+
+// Formats registered later take precedence, so a caller can replace a
+// built-in reader or writer by registering its own under the same name.
+pub struct FormatRegistry {
+    readers: Vec<Box<MeshReader>>,
+    writers: Vec<Box<MeshWriter>>,
+}
+
+impl FormatRegistry {
+    pub fn new() -> FormatRegistry {
+        FormatRegistry { readers: Vec::new(), writers: Vec::new() }
+    }
+
+    // A registry holding every format this crate knows. STL is registered
+    // first so that its loose sniffing is tried last.
+    pub fn with_defaults() -> FormatRegistry {
+        let mut r = FormatRegistry::new();
+        r.register_reader(Box::new(StlReader));
+        r.register_reader(Box::new(AmfReader));
+        r.register_reader(Box::new(ObjFormat));
+        r.register_reader(Box::new(OffFormat));
+        r.register_reader(Box::new(PlyReader));
+        r.register_reader(Box::new(ThreeMfFormat));
+
+        r.register_writer(Box::new(StlWriter {
+            ascii: false, solid_name: "meshman".to_string(), float_format: FloatFormat::Shortest,
+        }));
+        r.register_writer(Box::new(AmfWriter { compress: false }));
+        r.register_writer(Box::new(ObjFormat));
+        r.register_writer(Box::new(OffFormat));
+        r.register_writer(Box::new(PlyWriter { encoding: PlyEncoding::BinaryLittleEndian }));
+        r.register_writer(Box::new(ThreeMfFormat));
+        r.register_writer(Box::new(GltfWriter));
+        r.register_writer(Box::new(GlbWriter));
+        r.register_writer(Box::new(PovWriter));
+        r
+    }
+
+    pub fn register_reader(&mut self, reader: Box<MeshReader>) {
+        self.readers.push(reader);
+    }
+
+    pub fn register_writer(&mut self, writer: Box<MeshWriter>) {
+        self.writers.push(writer);
+    }
+
+    pub fn reader(&self, name: &str) -> Option<&MeshReader> {
+        self.readers.iter().rev().find(|r| r.name() == name).map(|r| &**r)
+    }
+
+    pub fn reader_for_extension(&self, ext: &str) -> Option<&MeshReader> {
+        let ext = ext.to_lowercase();
+        self.readers.iter().rev().find(|r| handles(r.extensions(), &ext)).map(|r| &**r)
+    }
+
+    pub fn reader_for_data(&self, data: &[u8]) -> Option<&MeshReader> {
+        self.readers.iter().rev().find(|r| r.sniff(data)).map(|r| &**r)
+    }
+
+    pub fn writer(&self, name: &str) -> Option<&MeshWriter> {
+        self.writers.iter().rev().find(|w| w.name() == name).map(|w| &**w)
+    }
+
+    pub fn writer_for_extension(&self, ext: &str) -> Option<&MeshWriter> {
+        let ext = ext.to_lowercase();
+        self.writers.iter().rev().find(|w| handles(w.extensions(), &ext)).map(|w| &**w)
+    }
+
+    // Reads a mesh, choosing the reader by extension when one is given and
+    // known, and by the contents otherwise.
+    pub fn read(&self, data: &[u8], ext: Option<&str>) -> MeshResult<Mesh> {
+        let reader = match ext.and_then(|e| self.reader_for_extension(e)) {
+            Some(r) => r,
+            None => match self.reader_for_data(data) {
+                Some(r) => r,
+                None => return Err(unsupported("could not recognise the input format".to_string())),
+            },
+        };
+        reader.read(&mut BufReader::new(data))
+    }
+
+    // Reads a mesh with the reader of the given name, whatever the data.
+    pub fn read_as(&self, data: &[u8], name: &str) -> MeshResult<Mesh> {
+        match self.reader(name) {
+            Some(reader) => reader.read(&mut BufReader::new(data)),
+            None => Err(unsupported(format!("no reader named '{}'", name))),
+        }
+    }
+
+    // Reads a file with the named reader, or else the one for its extension,
+    // or else the one that recognises its contents.
+    pub fn read_file(&self, path: &Path, name: Option<&str>) -> MeshResult<Mesh> {
+        let reader = match name {
+            Some(name) => match self.reader(name) {
+                Some(r) => r,
+                None => return Err(unsupported(format!("no reader named '{}'", name))),
+            },
+            None => match path.extension_str().and_then(|e| self.reader_for_extension(e)) {
+                Some(r) => r,
+                None => return self.read(&try!(File::open(path).read_to_end()), None),
+            },
+        };
+        reader.read_file(path)
+    }
+
+    // Writes with the writer of the given name, or else for that extension.
+    pub fn write(&self, m: &Mesh, w: &mut Writer, format: &str) -> MeshResult<()> {
+        match self.writer(format).or_else(|| self.writer_for_extension(format)) {
+            Some(writer) => writer.write(m, w),
+            None => Err(unsupported(format!("no writer for '{}'", format))),
+        }
+    }
+}
+
+fn handles(extensions: &[&str], ext: &str) -> bool {
+    extensions.iter().any(|e| *e == ext)
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|w| w == needle)
+}
+
+fn unsupported(msg: String) -> MeshError {
+    MeshError::UnsupportedFormat(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::Mesh;
+    use error::{MeshError,MeshResult};
+    use std::old_io::{File,MemWriter,Reader,TempDir,Writer};
+    use std::old_path::Path;
+
+    #[test]
+    fn sniffs_formats_without_extension() {
+        let registry = FormatRegistry::with_defaults();
+        let cases = [
+            ("solid x\nendsolid x\n", "stl"),
+            ("ply\nformat ascii 1.0\nend_header\n", "ply"),
+            ("COFF\n0 0 0\n", "off"),
+            ("# exported\nv 0 0 0\n", "obj"),
+            ("<?xml version='1.0'?>\n<amf unit='inch'></amf>", "amf"),
+        ];
+        for &(data, name) in cases.iter() {
+            assert_eq!(registry.reader_for_data(data.as_bytes()).unwrap().name(), name);
+        }
+    }
+
+    #[test]
+    fn extension_wins_and_round_trips() {
+        let registry = FormatRegistry::with_defaults();
+        let off = "OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        let mesh = registry.read(off.as_bytes(), Some("OFF")).unwrap();
+        for &ext in ["stl", "amf", "obj", "off", "ply", "3mf"].iter() {
+            let mut w = MemWriter::new();
+            registry.write(&mesh, &mut w, ext).unwrap();
+            let data = w.into_inner();
+            assert_eq!(registry.read(&data, Some(ext)).unwrap().facets.len(), 1);
+            assert_eq!(registry.read(&data, None).unwrap().facets.len(), 1);
+        }
+        assert!(registry.write(&mesh, &mut MemWriter::new(), "dxf").is_err());
+    }
+
+    #[test]
+    fn writers_reject_invalid_indices() {
+        let registry = FormatRegistry::with_defaults();
+        let off = "OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        let mut mesh = registry.read(off.as_bytes(), Some("off")).unwrap();
+        mesh.facets[0].v3 = 3;
+        for &ext in ["stl", "amf", "obj", "off", "ply", "3mf", "gltf", "glb", "pov"].iter() {
+            match registry.write(&mesh, &mut MemWriter::new(), ext) {
+                Err(MeshError::InvalidIndex { .. }) => {},
+                other => panic!("{} writer returned {:?}", ext, other),
+            }
+        }
+    }
+
+    struct Empty;
+
+    const EMPTY_EXTENSIONS: &'static [&'static str] = &["stl", "nothing"];
+
+    impl MeshReader for Empty {
+        fn name(&self) -> &'static str { "empty" }
+        fn extensions(&self) -> &'static [&'static str] { EMPTY_EXTENSIONS }
+        fn sniff(&self, _: &[u8]) -> bool { false }
+        fn read(&self, _: &mut Reader) -> MeshResult<Mesh> { Ok(Mesh::new()) }
+    }
+
+    #[test]
+    fn registered_formats_take_precedence() {
+        let mut registry = FormatRegistry::with_defaults();
+        registry.register_reader(Box::new(Empty));
+        assert_eq!(registry.reader_for_extension("STL").unwrap().name(), "empty");
+        assert_eq!(registry.reader_for_extension("nothing").unwrap().name(), "empty");
+        assert_eq!(registry.reader("stl").unwrap().name(), "stl");
+        let mesh = registry.read_as("solid x\nendsolid x\n".as_bytes(), "empty").unwrap();
+        assert_eq!(mesh.facets.len(), 0);
+        assert!(registry.read_as(&[], "dxf").is_err());
+    }
+
+    #[test]
+    fn files_are_read_by_extension_or_contents() {
+        let registry = FormatRegistry::with_defaults();
+        let path = Path::new("./Lily_Pad.stl");
+        let data = File::open(&path).read_to_end().unwrap();
+        let mesh = registry.read_file(&path, None).unwrap();
+        assert_eq!(mesh, registry.read(&data, Some("stl")).unwrap());
+
+        let dir = TempDir::new("meshman").unwrap();
+        let renamed = dir.path().join("lily_pad.data");
+        File::create(&renamed).write_all(&data).unwrap();
+        assert_eq!(registry.read_file(&renamed, None).unwrap(), mesh);
+        assert_eq!(registry.read_file(&renamed, Some("stl")).unwrap(), mesh);
+        assert!(registry.read_file(&renamed, Some("dxf")).is_err());
+    }
+}
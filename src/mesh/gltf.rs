@@ -0,0 +1,288 @@
+// glTF 2.0 export, as .gltf JSON plus .bin buffer or as a single .glb.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::old_io::{MemWriter,Writer};
+use std::f32;
+use std::num::Float;
+use mesh::Mesh;
+use vector::Vector3D;
+use unit::Unit;
+use error::{MeshError,MeshResult};
+
+const GLB_MAGIC: u32 = 0x46546C67;      // "glTF"
+const CHUNK_JSON: u32 = 0x4E4F534A;     // "JSON"
+const CHUNK_BIN: u32 = 0x004E4942;      // "BIN\0"
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+pub struct GltfFile;
+
+impl GltfFile {
+
+    // Writes the JSON document to `json` and the geometry to `bin`, which
+    // the document refers to as `bin_uri`.
+    pub fn write_gltf(m: &Mesh, json: &mut Writer, bin: &mut Writer, bin_uri: &str) -> MeshResult<()> {
+        let buffer = try!(geometry(m));
+        try!(json.write_str(&document(m, buffer.len(), Some(bin_uri))));
+        Ok(try!(bin.write_all(&buffer)))
+    }
+
+    // A single .gltf with the buffer embedded as a base64 data URI.
+    pub fn write_embedded(m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        let buffer = try!(geometry(m));
+        let uri = format!("data:application/octet-stream;base64,{}", base64(&buffer));
+        Ok(try!(w.write_str(&document(m, buffer.len(), Some(&uri)))))
+    }
+
+    pub fn write_glb(m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        let mut buffer = try!(geometry(m));
+        let mut json = document(m, buffer.len(), None).into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        while buffer.len() % 4 != 0 {
+            buffer.push(0);
+        }
+        try!(w.write_le_u32(GLB_MAGIC));
+        try!(w.write_le_u32(2));
+        try!(w.write_le_u32((12 + 8 + json.len() + 8 + buffer.len()) as u32));
+        try!(w.write_le_u32(json.len() as u32));
+        try!(w.write_le_u32(CHUNK_JSON));
+        try!(w.write_all(&json));
+        try!(w.write_le_u32(buffer.len() as u32));
+        try!(w.write_le_u32(CHUNK_BIN));
+        Ok(try!(w.write_all(&buffer)))
+    }
+}
+
+// Buffer layout: positions, normals, optional RGBA colors (all float
+// vectors, one per vertex), then u32 triangle indices. Every section is a
+// multiple of four bytes long, so no padding is needed between them.
+// Accessors may not be empty, so a mesh without facets cannot be written.
+fn geometry(m: &Mesh) -> MeshResult<Vec<u8>> {
+    try!(m.check_indices());
+    if m.facets.is_empty() {
+        return Err(MeshError::InvalidMesh("glTF needs at least one triangle".to_string()));
+    }
+    let mut w = MemWriter::new();
+    for v in positions(m).iter() {
+        try!(write_vector(&mut w, *v));
+    }
+    for n in normals(m).iter() {
+        try!(write_vector(&mut w, *n));
+    }
+    for c in m.vertex_colors.iter() {
+        try!(w.write_le_f32(c.r));
+        try!(w.write_le_f32(c.g));
+        try!(w.write_le_f32(c.b));
+        try!(w.write_le_f32(c.a));
+    }
+    for f in m.facets.iter() {
+        try!(w.write_le_u32(f.v1 as u32));
+        try!(w.write_le_u32(f.v2 as u32));
+        try!(w.write_le_u32(f.v3 as u32));
+    }
+    Ok(w.into_inner())
+}
+
+// glTF lengths are in metres. Meshes that do not know their unit are
+// written as they are.
+fn positions(m: &Mesh) -> Vec<Vector3D> {
+    match m.unit {
+        Some(u) => {
+            let k = u.factor_to(Unit::Meter) as f32;
+            m.vertices.iter().map(|v| v.scale(k)).collect()
+        },
+        None => m.vertices.clone(),
+    }
+}
+
+// NORMAL must hold unit vectors, which normals read from a file need not be.
+fn normals(m: &Mesh) -> Vec<Vector3D> {
+    m.smooth_vertex_normals().iter().map(|n| {
+        if n.length() == 0.0 {
+            Vector3D { x: 0.0, y: 0.0, z: 1.0 }
+        } else {
+            n.normalize()
+        }
+    }).collect()
+}
+
+fn write_vector(w: &mut Writer, v: Vector3D) -> MeshResult<()> {
+    try!(w.write_le_f32(v.x));
+    try!(w.write_le_f32(v.y));
+    Ok(try!(w.write_le_f32(v.z)))
+}
+
+fn document(m: &Mesh, buffer_len: usize, uri: Option<&str>) -> String {
+    let vertex_count = m.vertices.len();
+    let vec3_len = vertex_count * 12;
+    let colors = !m.vertex_colors.is_empty();
+    let colors_len = if colors { vertex_count * 16 } else { 0 };
+    let index_offset = 2 * vec3_len + colors_len;
+
+    let mut min = Vector3D { x: f32::INFINITY, y: f32::INFINITY, z: f32::INFINITY };
+    let mut max = Vector3D { x: f32::NEG_INFINITY, y: f32::NEG_INFINITY, z: f32::NEG_INFINITY };
+    for v in positions(m).iter() {
+        min = Vector3D { x: min.x.min(v.x), y: min.y.min(v.y), z: min.z.min(v.z) };
+        max = Vector3D { x: max.x.max(v.x), y: max.y.max(v.y), z: max.z.max(v.z) };
+    }
+
+    let mut views = vec![
+        view(0, vec3_len, ARRAY_BUFFER),
+        view(vec3_len, vec3_len, ARRAY_BUFFER),
+    ];
+    let mut accessors = vec![
+        format!("{{\"bufferView\":0,\"componentType\":{},\"count\":{},\"type\":\"VEC3\",\
+                 \"min\":[{},{},{}],\"max\":[{},{},{}]}}",
+                FLOAT, vertex_count, min.x, min.y, min.z, max.x, max.y, max.z),
+        format!("{{\"bufferView\":1,\"componentType\":{},\"count\":{},\"type\":\"VEC3\"}}",
+                FLOAT, vertex_count),
+    ];
+    let mut attributes = vec!["\"POSITION\":0".to_string(), "\"NORMAL\":1".to_string()];
+    if colors {
+        views.push(view(2 * vec3_len, colors_len, ARRAY_BUFFER));
+        accessors.push(format!("{{\"bufferView\":2,\"componentType\":{},\"count\":{},\"type\":\"VEC4\"}}",
+                               FLOAT, vertex_count));
+        attributes.push("\"COLOR_0\":2".to_string());
+    }
+    views.push(view(index_offset, m.facets.len() * 12, ELEMENT_ARRAY_BUFFER));
+    accessors.push(format!("{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"SCALAR\"}}",
+                           views.len() - 1, UNSIGNED_INT, m.facets.len() * 3));
+
+    let buffer = match uri {
+        Some(uri) => format!("{{\"uri\":\"{}\",\"byteLength\":{}}}", json_escape(uri), buffer_len),
+        None => format!("{{\"byteLength\":{}}}", buffer_len),
+    };
+    format!("{{\"asset\":{{\"version\":\"2.0\",\"generator\":\"meshman\"}},\
+             \"scene\":0,\"scenes\":[{{\"nodes\":[0]}}],\"nodes\":[{{\"mesh\":0}}],\
+             \"meshes\":[{{\"primitives\":[{{\"attributes\":{{{}}},\"indices\":{},\"mode\":4}}]}}],\
+             \"accessors\":[{}],\"bufferViews\":[{}],\"buffers\":[{}]}}\n",
+            attributes.connect(","), accessors.len() - 1,
+            accessors.connect(","), views.connect(","), buffer)
+}
+
+fn view(offset: usize, len: usize, target: u32) -> String {
+    format!("{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{},\"target\":{}}}", offset, len, target)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Color,Facet,Mesh};
+    use unit::Unit;
+    use vector::Vector3D;
+    use error::MeshError;
+    use std::old_io::{BufReader,MemWriter,Reader};
+
+    fn triangle() -> Mesh {
+        let mut m = Mesh::new_from_parts(vec![
+            Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+            Vector3D { x: 2.0, y: 0.0, z: 0.0 },
+            Vector3D { x: 0.0, y: 3.0, z: -1.0 },
+        ], vec![Facet { v1: 0, v2: 1, v3: 2, n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } }]);
+        m.calculate_normals();
+        m
+    }
+
+    #[test]
+    fn gltf_has_bounds_and_buffer() {
+        let mut json = MemWriter::new();
+        let mut bin = MemWriter::new();
+        GltfFile::write_gltf(&triangle(), &mut json, &mut bin, "tri.bin").unwrap();
+        let json = String::from_utf8(json.into_inner()).unwrap();
+        assert!(json.contains("\"min\":[0,0,-1],\"max\":[2,3,0]"));
+        assert!(json.contains("\"uri\":\"tri.bin\",\"byteLength\":84"));
+        assert!(!json.contains("COLOR_0"));
+        assert_eq!(bin.into_inner().len(), 84);
+    }
+
+    #[test]
+    fn positions_are_in_meters() {
+        let mut m = triangle();
+        m.unit = Some(Unit::Millimeter);
+        let mut json = MemWriter::new();
+        GltfFile::write_gltf(&m, &mut json, &mut MemWriter::new(), "tri.bin").unwrap();
+        let json = String::from_utf8(json.into_inner()).unwrap();
+        assert!(json.contains("\"min\":[0,0,-0.001],\"max\":[0.002,"));
+    }
+
+    #[test]
+    fn normals_are_unit_length() {
+        let mut m = triangle();
+        m.vertex_normals = vec![Vector3D { x: 0.0, y: 0.0, z: 2.0 }, Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+                                Vector3D { x: 3.0, y: 0.0, z: 4.0 }];
+        let mut bin = MemWriter::new();
+        GltfFile::write_gltf(&m, &mut MemWriter::new(), &mut bin, "tri.bin").unwrap();
+        let data = bin.into_inner();
+        let r = &mut BufReader::new(&data[36..72]);
+        let xs: Vec<f32> = (0..9).map(|_| r.read_le_f32().unwrap()).collect();
+        assert_eq!(xs, vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.6, 0.0, 0.8]);
+    }
+
+    #[test]
+    fn empty_meshes_are_rejected() {
+        let err = GltfFile::write_glb(&Mesh::new(), &mut MemWriter::new()).err().unwrap();
+        assert_eq!(err, MeshError::InvalidMesh("glTF needs at least one triangle".to_string()));
+    }
+
+    #[test]
+    fn base64_pads() {
+        assert_eq!(super::base64(b"meshma"), "bWVzaG1h");
+        assert_eq!(super::base64(b"mesh"), "bWVzaA==");
+        assert_eq!(super::base64(b"meshm"), "bWVzaG0=");
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned() {
+        let mut m = triangle();
+        m.vertex_colors = vec![Color::white(); 3];
+        let mut w = MemWriter::new();
+        GltfFile::write_glb(&m, &mut w).unwrap();
+        let data = w.into_inner();
+        let r = &mut BufReader::new(&data);
+        assert_eq!(r.read_le_u32().unwrap(), 0x46546C67);
+        assert_eq!(r.read_le_u32().unwrap(), 2);
+        assert_eq!(r.read_le_u32().unwrap() as usize, data.len());
+        let json_len = r.read_le_u32().unwrap() as usize;
+        assert_eq!(json_len % 4, 0);
+        r.read_le_u32().unwrap();
+        let json = String::from_utf8(r.read_exact(json_len).unwrap()).unwrap();
+        assert!(json.contains("\"COLOR_0\":2"));
+        assert_eq!(r.read_le_u32().unwrap(), 3 * 12 * 2 + 3 * 16 + 12);
+    }
+}
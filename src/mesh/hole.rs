@@ -0,0 +1,605 @@
+// Closing holes: boundary loops are triangulated and the patches added to
+// the mesh, then optionally refined and faired so they follow the surface
+// around them.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::collections::HashMap;
+use std::f32;
+use std::f32::consts::PI;
+use std::mem;
+use std::num::Float;
+use mesh::{Facet,Mesh};
+use topology::Topology;
+use validate::is_degenerate;
+use vector::Vector3D;
+use error::MeshResult;
+
+// Refinement splits a triangle while its centroid is further than the local
+// edge length over this from each corner, as in Liepa's paper.
+const DENSITY: f32 = 1.414214;
+const REFINE_ROUNDS: usize = 8;
+const FAIRING_STEPS: usize = 50;
+// Angles closer than this count as equal and the area decides, so a flat
+// hole gets its smallest patch rather than one picked by rounding noise.
+const ANGLE_TOLERANCE: f32 = 1e-3;
+
+// What the triangulation of a hole minimises.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Weighting {
+    // The total area.
+    MinArea,
+    // The sharpest fold between neighbouring facets, patch and mesh alike,
+    // and then the area.
+    MinDihedral,
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct HoleOptions {
+    // Loops with more edges are taken to be openings meant to be there.
+    pub max_edges: usize,
+    pub weighting: Weighting,
+    // Adds vertices inside a patch until its edges are about as long as the
+    // ones around the hole.
+    pub refine: bool,
+    // Moves the vertices refinement added so the patch carries on the
+    // curvature around it; without refinement there are none to move.
+    pub fair: bool,
+}
+
+impl HoleOptions {
+    pub fn new() -> HoleOptions {
+        HoleOptions { max_edges: 16, weighting: Weighting::MinDihedral, refine: true, fair: true }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct HoleReport {
+    pub holes_filled: usize,
+    pub facets_added: usize,
+    pub vertices_added: usize,
+    // Loops left open because they were longer than the limit, or touch
+    // themselves.
+    pub holes_skipped: usize,
+}
+
+// Fills every closed boundary loop of at most `opts.max_edges` edges.
+// Patches are wound against the loop, so they face the same way as the
+// facets around them, and take their colour and attribute from the facet on
+// the loop's first edge.
+pub fn fill_holes(mesh: &mut Mesh, opts: &HoleOptions) -> MeshResult<HoleReport> {
+    try!(mesh.check_indices());
+    let topo = try!(Topology::new(mem::replace(mesh, Mesh::new())));
+    let mut report = HoleReport { holes_filled: 0, facets_added: 0, vertices_added: 0, holes_skipped: 0 };
+    let mut patches: Vec<(Patch, usize)> = Vec::new();
+    for hole in topo.boundary_loops().into_iter() {
+        let first = match closed_loop(&topo, &hole) {
+            Some(f) => f,
+            None => continue,
+        };
+        if hole.len() > opts.max_edges || has_repeats(&hole) {
+            report.holes_skipped += 1;
+            continue;
+        }
+        patches.push((patch(&topo, hole, opts), first));
+    }
+    *mesh = topo.into_mesh();
+    for (patch, first) in patches.into_iter() {
+        report.holes_filled += 1;
+        report.facets_added += patch.triangles.len();
+        report.vertices_added += patch.points.len() - patch.hole.len();
+        add_patch(mesh, patch, first);
+    }
+    Ok(report)
+}
+
+// The facet on the loop's first edge when the loop closes on itself.
+fn closed_loop(topo: &Topology, hole: &[usize]) -> Option<usize> {
+    if hole.len() < 3 {
+        return None;
+    }
+    let last = hole[hole.len() - 1];
+    match topo.edge_between(last, hole[0]) {
+        Some(e) if e.half_edges.len() == 1 && topo.half_edges()[e.half_edges[0]].origin == last => {
+            topo.edge_between(hole[0], hole[1]).map(|e| topo.half_edges()[e.half_edges[0]].facet)
+        },
+        _ => None,
+    }
+}
+
+fn has_repeats(hole: &[usize]) -> bool {
+    hole.iter().enumerate().any(|(i, v)| hole[i + 1..].contains(v))
+}
+
+// The filling of one hole. The loop's vertices come first in `points`,
+// followed by any refinement adds; triangles index into `points` and face
+// the same way as the mesh.
+struct Patch {
+    hole: Vec<usize>,
+    points: Vec<Vector3D>,
+    triangles: Vec<[usize; 3]>,
+}
+
+fn patch(topo: &Topology, hole: Vec<usize>, opts: &HoleOptions) -> Patch {
+    let n = hole.len();
+    let points: Vec<Vector3D> = hole.iter().map(|&v| topo.mesh().vertices[v]).collect();
+    let triangles = match opts.weighting {
+        Weighting::MinArea => triangulate(&points),
+        Weighting::MinDihedral => {
+            // The mesh runs each loop edge the way the loop does, so a
+            // triangle wound along the loop continues the surface when its
+            // normal is the opposite of the facet's beyond the edge.
+            let vertices = &topo.mesh().vertices;
+            let outside: Vec<Vector3D> = (0..n).map(|k| {
+                let f = topo.mesh().facets[topo.edge_facets(hole[k], hole[(k + 1) % n])[0]];
+                normal(vertices[f.v1], vertices[f.v2], vertices[f.v3]).scale(-1.0)
+            }).collect();
+            triangulate_min_dihedral(&points, &outside)
+        },
+    };
+    let mut patch = Patch {
+        hole: hole,
+        points: points,
+        triangles: triangles.iter().map(|&(i, m, j)| [j, m, i]).collect(),
+    };
+    if opts.refine {
+        refine(&mut patch, topo);
+        if opts.fair && patch.points.len() > n {
+            fair(&mut patch, topo);
+        }
+    }
+    patch
+}
+
+// Appends the patch's vertices and facets. Added vertices get a normal from
+// the facets around them and the colour of the loop's first vertex when the
+// mesh carries those; facets copy the per-facet data of facet `like`.
+fn add_patch(mesh: &mut Mesh, patch: Patch, like: usize) {
+    let n = patch.hole.len();
+    let added = patch.points.len() - n;
+    let base = mesh.vertices.len();
+    mesh.vertices.push_all(&patch.points[n..]);
+    let facets: Vec<Facet> = {
+        let index = |k: usize| if k < n { patch.hole[k] } else { base + k - n };
+        patch.triangles.iter().map(|t| facet(&mesh.vertices, index(t[0]), index(t[1]), index(t[2]))).collect()
+    };
+    if !mesh.vertex_normals.is_empty() {
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut sums = vec![zero; added];
+        for t in patch.triangles.iter() {
+            let (a, b, c) = (patch.points[t[0]], patch.points[t[1]], patch.points[t[2]]);
+            // Twice the area, so larger facets count for more.
+            let cross = Vector3D::cross(b.minus(a), c.minus(a));
+            for &k in t.iter().filter(|&&k| k >= n) {
+                sums[k - n] = sums[k - n].plus(cross);
+            }
+        }
+        mesh.vertex_normals.extend(sums.iter().map(|s| s.normalize()));
+    }
+    if !mesh.vertex_colors.is_empty() {
+        let c = mesh.vertex_colors[patch.hole[0]];
+        for _ in 0..added {
+            mesh.vertex_colors.push(c);
+        }
+    }
+    add_facets(mesh, facets, like);
+}
+
+fn facet(vertices: &[Vector3D], a: usize, b: usize, c: usize) -> Facet {
+    Facet { v1: a, v2: b, v3: c, n: normal(vertices[a], vertices[b], vertices[c]) }
+}
+
+// Appends facets, copying the per-facet data of facet `like` when the mesh
+// has any.
+fn add_facets(mesh: &mut Mesh, facets: Vec<Facet>, like: usize) {
+    for f in facets.into_iter() {
+        if !mesh.facet_colors.is_empty() {
+            let c = mesh.facet_colors[like];
+            mesh.facet_colors.push(c);
+        }
+        if !mesh.facet_attributes.is_empty() {
+            let a = mesh.facet_attributes[like];
+            mesh.facet_attributes.push(a);
+        }
+        mesh.facets.push(f);
+    }
+}
+
+// Triangulates a polygon given by its corners in order, choosing the
+// triangles with the least total area by dynamic programming over the
+// sub-polygons. Triangles come back as corner indices (i, m, j) with
+// i < m < j, wound the same way as the polygon. Triangles without area are
+// only used where nothing else will do.
+pub fn triangulate(points: &[Vector3D]) -> Vec<(usize, usize, usize)> {
+    triangulate_by(points, None)
+}
+
+// Like triangulate(), but first minimising the largest angle between the
+// normals of neighbouring triangles, Liepa's minimum dihedral weighting.
+// outside[k] is the normal a triangle on the edge from corner k to the next
+// should have to continue the surface beyond that edge, or zero where any
+// will do.
+pub fn triangulate_min_dihedral(points: &[Vector3D], outside: &[Vector3D]) -> Vec<(usize, usize, usize)> {
+    triangulate_by(points, Some(outside))
+}
+
+fn triangulate_by(points: &[Vector3D], outside: Option<&[Vector3D]>) -> Vec<(usize, usize, usize)> {
+    let n = points.len();
+    let mut triangles = Vec::new();
+    if n < 3 {
+        return triangles;
+    }
+    // cost[i][j], split[i][j] and normals[i][j] describe the best
+    // triangulation of the polygon i, i + 1, ..., j closed by the edge from
+    // j back to i: its largest angle and its area, the corner its triangle
+    // on that edge uses, and that triangle's normal.
+    let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+    let mut cost = vec![vec![(0.0f32, 0.0f32); n]; n];
+    let mut split: Vec<Vec<usize>> = (0..n).map(|i| vec![i + 1; n]).collect();
+    let mut normals = vec![vec![zero; n]; n];
+    for len in 2..n {
+        for i in 0..n - len {
+            let j = i + len;
+            let mut best = (f32::INFINITY, f32::INFINITY);
+            for m in i + 1..j {
+                let (a, b, c) = (points[i], points[m], points[j]);
+                if is_degenerate(a, b, c) {
+                    continue;
+                }
+                let nm = normal(a, b, c);
+                let mut worst = cost[i][m].0.max(cost[m][j].0);
+                if let Some(outside) = outside {
+                    let left = if m == i + 1 { outside[i] } else { normals[i][m] };
+                    let right = if j == m + 1 { outside[m] } else { normals[m][j] };
+                    worst = worst.max(angle(nm, left)).max(angle(nm, right));
+                    if i == 0 && j == n - 1 {
+                        worst = worst.max(angle(nm, outside[j]));
+                    }
+                }
+                let candidate = (worst, cost[i][m].1 + cost[m][j].1 + area(a, b, c));
+                if better(candidate, best) {
+                    best = candidate;
+                    split[i][j] = m;
+                    normals[i][j] = nm;
+                }
+            }
+            cost[i][j] = best;
+        }
+    }
+    let mut stack = vec![(0, n - 1)];
+    while let Some((i, j)) = stack.pop() {
+        if j < i + 2 {
+            continue;
+        }
+        let m = split[i][j];
+        triangles.push((i, m, j));
+        stack.push((i, m));
+        stack.push((m, j));
+    }
+    triangles
+}
+
+fn better(a: (f32, f32), b: (f32, f32)) -> bool {
+    a.0 < b.0 - ANGLE_TOLERANCE || (a.0 <= b.0 + ANGLE_TOLERANCE && a.1 < b.1)
+}
+
+// Splits triangles at their centroid while that keeps the new edges about
+// as long as the ones around them, and flips edges after each round to keep
+// the triangles well shaped. The edge length at a loop vertex is the mean
+// of its two loop edges; an added vertex takes its triangle's mean.
+fn refine(patch: &mut Patch, topo: &Topology) {
+    let n = patch.hole.len();
+    let mut scale: Vec<f32> = (0..n).map(|k| {
+        let p = patch.points[k];
+        (p.minus(patch.points[(k + n - 1) % n]).length() + p.minus(patch.points[(k + 1) % n]).length()) / 2.0
+    }).collect();
+    for _ in 0..REFINE_ROUNDS {
+        let mut split = false;
+        for t in 0..patch.triangles.len() {
+            let tri = patch.triangles[t];
+            let c = patch.points[tri[0]].plus(patch.points[tri[1]]).plus(patch.points[tri[2]]).scale(1.0 / 3.0);
+            let s = (scale[tri[0]] + scale[tri[1]] + scale[tri[2]]) / 3.0;
+            let far = tri.iter().all(|&k| {
+                let d = DENSITY * c.minus(patch.points[k]).length();
+                d > s && d > scale[k]
+            });
+            if !far {
+                continue;
+            }
+            let p = patch.points.len();
+            patch.points.push(c);
+            scale.push(s);
+            patch.triangles[t] = [tri[0], tri[1], p];
+            patch.triangles.push([tri[1], tri[2], p]);
+            patch.triangles.push([tri[2], tri[0], p]);
+            split = true;
+        }
+        if !split {
+            break;
+        }
+        relax(patch, topo);
+    }
+}
+
+// Flips patch edges whose opposite angles add up to more than a half turn,
+// one at a time until none is left. Loop edges are never flipped, nor is an
+// edge whose flip would repeat one the patch or the mesh already has.
+fn relax(patch: &mut Patch, topo: &Topology) {
+    let n = patch.hole.len();
+    // A bent patch can flip back and forth, so the flips are capped.
+    for _ in 0..4 * patch.triangles.len() {
+        let mut edges = HashMap::new();
+        for (t, tri) in patch.triangles.iter().enumerate() {
+            for k in 0..3 {
+                edges.insert((tri[k], tri[(k + 1) % 3]), t);
+            }
+        }
+        let mut flip = None;
+        'search: for (t, tri) in patch.triangles.iter().enumerate() {
+            for k in 0..3 {
+                let (a, b, c) = (tri[k], tri[(k + 1) % 3], tri[(k + 2) % 3]);
+                let u = match edges.get(&(b, a)) {
+                    Some(&u) => u,
+                    None => continue,
+                };
+                let d = patch.triangles[u].iter().map(|&v| v).find(|&v| v != a && v != b).unwrap();
+                let p = &patch.points;
+                let opposite = angle(p[a].minus(p[c]), p[b].minus(p[c])) + angle(p[a].minus(p[d]), p[b].minus(p[d]));
+                if opposite <= PI + 1e-4 || c == d || edges.contains_key(&(c, d)) || edges.contains_key(&(d, c)) {
+                    continue;
+                }
+                if c < n && d < n && topo.edge_between(patch.hole[c], patch.hole[d]).is_some() {
+                    continue;
+                }
+                flip = Some((t, u, [a, d, c], [d, b, c]));
+                break 'search;
+            }
+        }
+        match flip {
+            Some((t, u, first, second)) => {
+                patch.triangles[t] = first;
+                patch.triangles[u] = second;
+            },
+            None => return,
+        }
+    }
+}
+
+// Moves the added vertices towards a zero bi-Laplacian, the umbrella
+// operator applied twice, with the loop and the mesh around it held in
+// place. The umbrella at a loop vertex takes in its mesh neighbours too,
+// which is what carries the surrounding curvature into the patch.
+fn fair(patch: &mut Patch, topo: &Topology) {
+    let n = patch.hole.len();
+    let count = patch.points.len();
+    // Mesh vertices next to the loop are appended to `points`.
+    let mut points = patch.points.clone();
+    let mut rings: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for tri in patch.triangles.iter() {
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            if !rings[a].contains(&b) {
+                rings[a].push(b);
+            }
+            if !rings[b].contains(&a) {
+                rings[b].push(a);
+            }
+        }
+    }
+    let mut fixed: HashMap<usize, usize> = HashMap::new();
+    for k in 0..n {
+        for w in topo.one_ring(patch.hole[k]).into_iter() {
+            let idx = match patch.hole.iter().position(|&h| h == w) {
+                Some(i) => i,
+                None => *fixed.entry(w).get().unwrap_or_else(|e| {
+                    points.push(topo.mesh().vertices[w]);
+                    e.insert(points.len() - 1)
+                }),
+            };
+            if !rings[k].contains(&idx) {
+                rings[k].push(idx);
+            }
+        }
+    }
+
+    let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+    let mean = |ring: &[usize], of: &[Vector3D]| {
+        ring.iter().fold(zero, |s, &w| s.plus(of[w])).scale(1.0 / ring.len() as f32)
+    };
+    for _ in 0..FAIRING_STEPS {
+        let umbrella: Vec<Vector3D> = (0..count).map(|v| mean(&rings[v], &points).minus(points[v])).collect();
+        for v in n..count {
+            let ring = &rings[v];
+            let bi = mean(ring, &umbrella).minus(umbrella[v]);
+            // The bi-Laplacian's own coefficient at v, so each step lands
+            // where it would vanish with the neighbours held still.
+            let weight = 1.0 + ring.iter().fold(0.0, |s, &w| s + 1.0 / rings[w].len() as f32) / ring.len() as f32;
+            points[v] = points[v].minus(bi.scale(1.0 / weight));
+        }
+    }
+    for v in n..count {
+        patch.points[v] = points[v];
+    }
+}
+
+// The angle between two directions, or zero when either is missing.
+fn angle(a: Vector3D, b: Vector3D) -> f32 {
+    let lengths = a.length() * b.length();
+    if lengths == 0.0 {
+        return 0.0;
+    }
+    (Vector3D::dot(a, b) / lengths).max(-1.0).min(1.0).acos()
+}
+
+// The unit normal of a triangle, or zero when it has no area.
+fn normal(a: Vector3D, b: Vector3D, c: Vector3D) -> Vector3D {
+    let n = Vector3D::cross(b.minus(a), c.minus(a));
+    if n.length() == 0.0 {
+        return n;
+    }
+    n.normalize()
+}
+
+fn area(a: Vector3D, b: Vector3D, c: Vector3D) -> f32 {
+    Vector3D::cross(b.minus(a), c.minus(a)).length() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::Float;
+    use mesh::{Color,Facet,Mesh};
+    use vector::Vector3D;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3D {
+        Vector3D { x: x, y: y, z: z }
+    }
+
+    fn f(a: usize, b: usize, c: usize) -> Facet {
+        Facet { v1: a, v2: b, v3: c, n: v(0.0, 0.0, 0.0) }
+    }
+
+    // A closed box with its top, the square 4-5-6-7, missing.
+    fn open_box() -> Mesh {
+        let vertices = vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(1.0, 1.0, 0.0), v(0.0, 1.0, 0.0),
+                            v(0.0, 0.0, 1.0), v(1.0, 0.0, 1.0), v(1.0, 1.0, 1.0), v(0.0, 1.0, 1.0)];
+        let facets = vec![f(0, 2, 1), f(0, 3, 2),
+                          f(0, 1, 5), f(0, 5, 4), f(1, 2, 6), f(1, 6, 5),
+                          f(2, 3, 7), f(2, 7, 6), f(3, 0, 4), f(3, 4, 7)];
+        Mesh::new_from_parts(vertices, facets)
+    }
+
+    // A flat 4 by 4 plate with the 2 by 2 square in its middle cut out.
+    fn plate_with_hole() -> Mesh {
+        let mut vertices = Vec::new();
+        for y in 0..5 {
+            for x in 0..5 {
+                vertices.push(v(x as f32, y as f32, 0.0));
+            }
+        }
+        let mut facets = Vec::new();
+        for y in 0..4usize {
+            for x in 0..4usize {
+                if (x == 1 || x == 2) && (y == 1 || y == 2) {
+                    continue;
+                }
+                let a = y * 5 + x;
+                facets.push(f(a, a + 1, a + 6));
+                facets.push(f(a, a + 6, a + 5));
+            }
+        }
+        Mesh::new_from_parts(vertices, facets)
+    }
+
+    // Part of a sphere of radius 10, 8 by 8 cells over 0.8 radians of
+    // latitude and longitude, with the middle 4 by 4 cut out.
+    fn sphere_with_hole() -> Mesh {
+        let mut vertices = Vec::new();
+        for i in 0..9 {
+            let lat = -0.4 + 0.1 * i as f32;
+            for j in 0..9 {
+                let lon = -0.4 + 0.1 * j as f32;
+                vertices.push(v(10.0 * lat.cos() * lon.sin(), 10.0 * lat.sin(), 10.0 * lat.cos() * lon.cos()));
+            }
+        }
+        let mut facets = Vec::new();
+        for i in 0..8usize {
+            for j in 0..8usize {
+                if i >= 2 && i < 6 && j >= 2 && j < 6 {
+                    continue;
+                }
+                let a = i * 9 + j;
+                facets.push(f(a, a + 1, a + 10));
+                facets.push(f(a, a + 10, a + 9));
+            }
+        }
+        Mesh::new_from_parts(vertices, facets)
+    }
+
+    #[test]
+    fn square_hole_gets_two_facets() {
+        let mut mesh = open_box();
+        mesh.facet_colors = vec![Color::white(); 10];
+        mesh.facet_colors[3] = Color::rgb(1.0, 0.0, 0.0);
+        let report = fill_holes(&mut mesh, &HoleOptions::new()).unwrap();
+        assert_eq!(report, HoleReport { holes_filled: 1, facets_added: 2, vertices_added: 0, holes_skipped: 0 });
+        assert!(mesh.validate().is_valid(), "{:?}", mesh.validate());
+        assert_eq!(mesh.facets[10].n, v(0.0, 0.0, 1.0));
+        assert_eq!(mesh.facet_colors.len(), 12);
+        assert_eq!(mesh.facet_colors[11], Color::rgb(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn long_holes_are_left_open() {
+        let mut mesh = open_box();
+        let report = fill_holes(&mut mesh, &HoleOptions { max_edges: 3, ..HoleOptions::new() }).unwrap();
+        assert_eq!(report, HoleReport { holes_filled: 0, facets_added: 0, vertices_added: 0, holes_skipped: 1 });
+        assert_eq!(mesh, open_box());
+    }
+
+    #[test]
+    fn refined_patch_matches_its_surroundings() {
+        let mut mesh = plate_with_hole();
+        // The plate's outer rim is a loop of 16 edges, and stays open.
+        let report = fill_holes(&mut mesh, &HoleOptions { max_edges: 8, ..HoleOptions::new() }).unwrap();
+        assert_eq!((report.holes_filled, report.holes_skipped), (1, 1));
+        assert!(report.vertices_added > 0);
+        assert_eq!(mesh.vertices.len(), 25 + report.vertices_added);
+        for p in mesh.vertices[25..].iter() {
+            assert!(p.x > 1.0 && p.x < 3.0 && p.y > 1.0 && p.y < 3.0 && p.z.abs() < 1e-5, "{:?}", p);
+        }
+        for f in mesh.facets[24..].iter() {
+            assert!(f.n.z > 0.999, "{:?}", f);
+        }
+        let validation = mesh.validate();
+        assert!(validation.degenerate_facets.is_empty());
+        assert!(validation.inconsistent_facets.is_empty());
+        assert!(validation.non_manifold_edges.is_empty());
+        assert_eq!(validation.holes.len(), 1);
+    }
+
+    #[test]
+    fn faired_patch_follows_the_curvature() {
+        // How far the added vertices are from the sphere, on average and
+        // at worst. Without fairing they sit on the flat triangulation.
+        let deviation = |fair: bool| {
+            let mut mesh = sphere_with_hole();
+            let report = fill_holes(&mut mesh, &HoleOptions { fair: fair, ..HoleOptions::new() }).unwrap();
+            assert_eq!((report.holes_filled, report.holes_skipped), (1, 1));
+            assert!(report.vertices_added > 0);
+            let d: Vec<f32> = mesh.vertices[81..].iter().map(|p| (p.length() - 10.0).abs()).collect();
+            (d.iter().fold(0.0, |s, x| s + *x) / d.len() as f32, d.iter().fold(0.0, |m: f32, x| m.max(*x)))
+        };
+        let (flat_mean, _) = deviation(false);
+        let (faired_mean, faired_worst) = deviation(true);
+        assert!(faired_worst < 0.05, "{}", faired_worst);
+        assert!(4.0 * faired_mean < flat_mean, "{} against {}", faired_mean, flat_mean);
+    }
+
+    #[test]
+    fn least_area_triangulation() {
+        // An arrowhead, dented at 3: the diagonal 1-3 lies inside it and
+        // makes the smaller pair of triangles.
+        let points = [v(0.0, 0.0, 0.0), v(2.0, -0.2, 0.0), v(4.0, 0.0, 0.0), v(2.0, -0.1, 0.0)];
+        let mut triangles = triangulate(&points);
+        triangles.sort();
+        assert_eq!(triangles, vec![(0, 1, 3), (1, 2, 3)]);
+    }
+
+    #[test]
+    fn least_dihedral_triangulation_follows_the_fold() {
+        // A gap across the edge 5-2 of a box, with the top on one side and
+        // a front leaning out a little on the other. Cutting the corner
+        // saves area, but only the edge itself keeps both faces flat.
+        let points = [v(0.0, 0.5, 1.0), v(1.0, 0.5, 1.0), v(1.0, 0.0, 1.0),
+                      v(1.0, -0.05, 0.5), v(0.0, -0.05, 0.5), v(0.0, 0.0, 1.0)];
+        let top = v(0.0, 0.0, -1.0);
+        let front = v(0.0, 0.5, -0.05).normalize();
+        let outside = [top, top, front, front, front, top];
+        let folds = |triangles: &[(usize, usize, usize)]| {
+            triangles.iter().any(|&(i, m, j)| [i, m, j].contains(&2) && [i, m, j].contains(&5))
+        };
+        assert!(!folds(&triangulate(&points)));
+        assert!(folds(&triangulate_min_dihedral(&points, &outside)));
+    }
+}
@@ -1,20 +1,58 @@
 #![crate_name = "mesh"]
-#![feature(core,old_io,old_path)]
+#![feature(collections,core,old_io,old_path,os,std_misc,rustc_private)]
+
+extern crate flate;
 
 // This tells the compiler to look in these additional files for code that's
 // part of this module.
+mod error;
+mod parse;
+mod format;
 mod vector;
 mod mesh;
+mod unit;
+mod weld;
+mod topology;
+mod validate;
+mod hole;
+mod repair;
 mod stl;
 mod pov;
 mod amf;
+mod obj;
+mod off;
+mod gltf;
+mod ply;
+mod threemf;
+mod xml;
+mod zip;
+mod mmap;
 
 // By default code in a sub-module isn't exposed when someone uses this crate;
 // this says to the compiler "I want to use this struct, but all export it to
 // people who use me".
+pub use self::error::{MeshError,MeshResult};
 pub use self::vector::Vector3D;
 pub use self::vector::VertexMap;
-pub use self::mesh::Mesh;
+pub use self::mesh::{Mesh,Facet,Color};
+pub use self::unit::Unit;
+pub use self::weld::WeldReport;
+pub use self::topology::{Topology,HalfEdge,Edge};
+pub use self::validate::ValidationReport;
+pub use self::hole::{HoleOptions,HoleReport,Weighting};
+pub use self::repair::{RepairOptions,RepairSummary};
 pub use self::stl::StlFile;
+pub use self::stl::{StlFacet,StlFacetReader,StlFacetWriter};
+pub use self::stl::StlFormat;
+pub use self::stl::StlColors;
+pub use self::stl::FloatFormat;
 pub use self::pov::POV;
-pub use self::amf::AmfFile;
+pub use self::amf::{AmfFile,AmfDocument,AmfMaterial,AmfObject,AmfVolume};
+pub use self::gltf::GltfFile;
+pub use self::obj::{ObjFile,ObjGroup,ObjFace};
+pub use self::off::OffFile;
+pub use self::threemf::{ThreeMfFile,ThreeMfMaterials,ThreeMfObject,ThreeMfItem};
+pub use self::ply::{PlyFile,PlyEncoding,PlyElement,PlyProperty,PlyType,PlyValue};
+pub use self::format::{MeshReader,MeshWriter,FormatRegistry};
+pub use self::format::{StlReader,StlWriter,AmfReader,AmfWriter,ObjFormat,OffFormat};
+pub use self::format::{PlyReader,PlyWriter,ThreeMfFormat,GltfWriter,GlbWriter,PovWriter};
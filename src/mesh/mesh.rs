@@ -2,9 +2,20 @@
 #![allow(dead_code)]
 
 use std::fmt;
+use std::num::Float;
 use vector::Vector3D;
+use error::{MeshError,MeshResult};
+use unit::Unit;
+use weld;
+use weld::WeldReport;
+use validate;
+use validate::ValidationReport;
+use repair;
+use repair::{RepairOptions,RepairSummary};
+use hole;
+use hole::{HoleOptions,HoleReport};
 
-#[derive(PartialEq, Eq, Hash, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Facet {
     pub v1: usize,
     pub v2: usize,
@@ -19,10 +30,64 @@ impl fmt::Debug for Facet {
     }
 }
 
+// RGBA color with components in 0..1.
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+// Same reasoning as for Vector3D: colors only ever come from parsed files.
+impl Eq for Color {
+    #[inline(always)]
+    fn assert_receiver_is_total_eq(&self) {}
+}
+
+impl fmt::Debug for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rgba({},{},{},{})", self.r, self.g, self.b, self.a)
+    }
+}
+
+impl Color {
+    pub fn rgb(r: f32, g: f32, b: f32) -> Color {
+        Color { r: r, g: g, b: b, a: 1.0 }
+    }
+
+    pub fn white() -> Color {
+        Color::rgb(1.0, 1.0, 1.0)
+    }
+
+    pub fn from_bytes(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r: r as f32 / 255.0, g: g as f32 / 255.0, b: b as f32 / 255.0, a: a as f32 / 255.0 }
+    }
+
+    // Components scaled to 0..255 and rounded: [r, g, b, a].
+    pub fn to_bytes(&self) -> [u8; 4] {
+        fn byte(c: f32) -> u8 {
+            (c.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+        }
+        [byte(self.r), byte(self.g), byte(self.b), byte(self.a)]
+    }
+}
+
+// The normal, colour and attribute lists are either empty or hold exactly one
+// entry per vertex (or per facet).
 #[derive(PartialEq, Eq, Debug)]
 pub struct Mesh {
     pub vertices: Vec<Vector3D>,
     pub facets: Vec<Facet>,
+    pub vertex_normals: Vec<Vector3D>,
+    pub vertex_colors: Vec<Color>,
+    pub facet_colors: Vec<Color>,
+    // The 80 byte header of the binary STL the mesh came from (or one to
+    // write), and the attribute word stored with each of its facets.
+    pub stl_header: Vec<u8>,
+    pub facet_attributes: Vec<u16>,
+    // None when the format the mesh came from does not say.
+    pub unit: Option<Unit>,
 }
 
 impl Mesh {
@@ -30,6 +95,12 @@ impl Mesh {
         Mesh {
             vertices: Vec::new(),
             facets: Vec::new(),
+            vertex_normals: Vec::new(),
+            vertex_colors: Vec::new(),
+            facet_colors: Vec::new(),
+            stl_header: Vec::new(),
+            facet_attributes: Vec::new(),
+            unit: None,
         }
     }
 
@@ -37,6 +108,149 @@ impl Mesh {
         Mesh {
             vertices: vs,
             facets: fs,
+            vertex_normals: Vec::new(),
+            vertex_colors: Vec::new(),
+            facet_colors: Vec::new(),
+            stl_header: Vec::new(),
+            facet_attributes: Vec::new(),
+            unit: None,
+        }
+    }
+
+    // Fails on the first facet that refers to a vertex the mesh lacks, so
+    // writers can report bad meshes instead of indexing out of bounds.
+    pub fn check_indices(&self) -> MeshResult<()> {
+        let n = self.vertices.len();
+        for (i, f) in self.facets.iter().enumerate() {
+            for &v in [f.v1, f.v2, f.v3].iter() {
+                if v >= n {
+                    return Err(MeshError::InvalidIndex { facet: i, index: v, vertices: n });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Keeps the facets `keep` accepts, along with their colours and
+    // attributes, and returns how many were removed.
+    pub fn retain_facets<F: FnMut(usize, &Facet) -> bool>(&mut self, mut keep: F) -> usize {
+        fn filter<T: Copy>(v: &mut Vec<T>, flags: &[bool]) {
+            if v.len() == flags.len() {
+                *v = v.iter().zip(flags.iter()).filter(|&(_, k)| *k).map(|(x, _)| *x).collect();
+            }
+        }
+        let flags: Vec<bool> = self.facets.iter().enumerate().map(|(i, f)| keep(i, f)).collect();
+        let before = self.facets.len();
+        filter(&mut self.facets, &flags);
+        filter(&mut self.facet_colors, &flags);
+        filter(&mut self.facet_attributes, &flags);
+        before - self.facets.len()
+    }
+
+    // Drops vertices no facet uses, along with their normals and colours,
+    // and returns how many went.
+    pub fn remove_unused_vertices(&mut self) -> usize {
+        let unused = self.vertices.len();
+        let mut used = vec![false; unused];
+        for f in self.facets.iter() {
+            used[f.v1] = true;
+            used[f.v2] = true;
+            used[f.v3] = true;
+        }
+        let mut remap = vec![unused; unused];
+        let mut next = 0;
+        for (i, u) in used.iter().enumerate() {
+            if *u {
+                remap[i] = next;
+                next += 1;
+            }
+        }
+        fn keep<T: Copy>(v: &mut Vec<T>, used: &[bool]) {
+            if v.len() == used.len() {
+                *v = v.iter().zip(used.iter()).filter(|&(_, u)| *u).map(|(x, _)| *x).collect();
+            }
+        }
+        keep(&mut self.vertices, &used);
+        keep(&mut self.vertex_normals, &used);
+        keep(&mut self.vertex_colors, &used);
+        for f in self.facets.iter_mut() {
+            f.v1 = remap[f.v1];
+            f.v2 = remap[f.v2];
+            f.v3 = remap[f.v3];
+        }
+        unused - next
+    }
+
+    // Merges vertices closer than `epsilon`; see weld::weld.
+    pub fn weld(&mut self, epsilon: f32) -> MeshResult<WeldReport> {
+        weld::weld(self, epsilon)
+    }
+
+    // Runs the steps `opts` asks for; see repair::repair.
+    pub fn repair(&mut self, opts: &RepairOptions) -> MeshResult<RepairSummary> {
+        repair::repair(self, opts)
+    }
+
+    // Closes the holes `opts` allows; see hole::fill_holes.
+    pub fn fill_holes(&mut self, opts: &HoleOptions) -> MeshResult<HoleReport> {
+        hole::fill_holes(self, opts)
+    }
+
+    // Runs every check in validate::validate.
+    pub fn validate(&self) -> ValidationReport {
+        validate::validate(self)
+    }
+
+    // Rescales the vertices from the mesh's unit to `to`. Normals keep their
+    // direction, so only positions change.
+    pub fn convert_units(&mut self, to: Unit) -> MeshResult<()> {
+        let from = match self.unit {
+            Some(u) => u,
+            None => return Err(MeshError::InvalidArgument(
+                format!("cannot convert to {}: the mesh's unit is unknown", to))),
+        };
+        let k = from.factor_to(to);
+        for v in self.vertices.iter_mut() {
+            *v = Vector3D { x: (v.x as f64 * k) as f32, y: (v.y as f64 * k) as f32, z: (v.z as f64 * k) as f32 };
+        }
+        self.unit = Some(to);
+        Ok(())
+    }
+
+    // Recomputes every facet normal from its vertices.
+    pub fn calculate_normals(&mut self) {
+        for f in self.facets.iter_mut() {
+            let a = self.vertices[f.v1];
+            let b = self.vertices[f.v2];
+            let c = self.vertices[f.v3];
+            f.n = Vector3D::cross(b.minus(a), c.minus(a)).normalize();
+        }
+    }
+
+    // Per-vertex normals: the ones the mesh carries, or else the area
+    // weighted average of the normals of the facets around each vertex.
+    pub fn smooth_vertex_normals(&self) -> Vec<Vector3D> {
+        if !self.vertex_normals.is_empty() {
+            return self.vertex_normals.clone();
+        }
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut sums = vec![zero; self.vertices.len()];
+        for f in self.facets.iter() {
+            let a = self.vertices[f.v1];
+            let b = self.vertices[f.v2];
+            let c = self.vertices[f.v3];
+            // The unnormalized cross product is twice the facet area.
+            let n = Vector3D::cross(b.minus(a), c.minus(a));
+            for &i in [f.v1, f.v2, f.v3].iter() {
+                sums[i] = sums[i].plus(n);
+            }
         }
+        sums.iter().map(|n| {
+            if n.x == 0.0 && n.y == 0.0 && n.z == 0.0 {
+                Vector3D { x: 0.0, y: 0.0, z: 1.0 }
+            } else {
+                n.normalize()
+            }
+        }).collect()
     }
 }
@@ -0,0 +1,96 @@
+// Read-only mappings of whole files, so big inputs can be decoded in place
+// rather than copied into a buffer first.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+#[cfg(unix)]
+pub use self::imp::MappedFile;
+
+#[cfg(not(unix))]
+pub use self::fallback::MappedFile;
+
+#[cfg(unix)]
+mod imp {
+    use std::old_io::{File,IoError,IoErrorKind};
+    use std::old_path::Path;
+    use std::os::{MapOption,MemoryMap};
+    use std::os::unix::AsRawFd;
+    use std::slice;
+    use error::{MeshError,MeshResult};
+
+    pub struct MappedFile {
+        // The descriptor stays open for as long as the mapping is in use.
+        _file: File,
+        // Empty files cannot be mapped, so they have no map at all.
+        map: Option<MemoryMap>,
+        len: usize,
+    }
+
+    impl MappedFile {
+        pub fn open(path: &Path) -> MeshResult<MappedFile> {
+            let file = try!(File::open(path));
+            let len = try!(file.stat()).size as usize;
+            let map = if len == 0 {
+                None
+            } else {
+                let options = [MapOption::MapReadable, MapOption::MapFd(file.as_raw_fd())];
+                match MemoryMap::new(len, &options) {
+                    Ok(m) => Some(m),
+                    Err(e) => return Err(MeshError::Io(IoError {
+                        kind: IoErrorKind::OtherIoError,
+                        desc: "could not map file",
+                        detail: Some(e.to_string()),
+                    })),
+                }
+            };
+            Ok(MappedFile { _file: file, map: map, len: len })
+        }
+
+        // The mapping is rounded up to whole pages; only the file's own
+        // length is handed out.
+        pub fn as_slice(&self) -> &[u8] {
+            match self.map {
+                Some(ref m) => unsafe { slice::from_raw_parts(m.data() as *const u8, self.len) },
+                None => &[],
+            }
+        }
+    }
+}
+
+// Without mmap support the file is simply read in.
+#[cfg(not(unix))]
+mod fallback {
+    use std::old_io::{File,Reader};
+    use std::old_path::Path;
+    use error::MeshResult;
+
+    pub struct MappedFile {
+        data: Vec<u8>,
+    }
+
+    impl MappedFile {
+        pub fn open(path: &Path) -> MeshResult<MappedFile> {
+            let data = try!(File::open(path).read_to_end());
+            Ok(MappedFile { data: data })
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            &self.data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::old_io::{File,Reader};
+    use std::old_path::Path;
+
+    #[test]
+    fn mapping_matches_reading() {
+        let path = Path::new("./Lily_Pad.stl");
+        let mapped = MappedFile::open(&path).unwrap();
+        let read = File::open(&path).read_to_end().unwrap();
+        assert_eq!(mapped.as_slice(), &read[..]);
+    }
+}
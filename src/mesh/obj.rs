@@ -0,0 +1,317 @@
+// Wavefront OBJ file handling.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::old_io::{Reader,Writer};
+use std::str;
+use mesh::{Color,Facet,Mesh};
+use vector::Vector3D;
+use parse::parse_error;
+use error::{MeshError,MeshResult};
+
+// One triangle, with zero-based indices into the file's vertex, texture
+// coordinate and normal lists.
+#[derive(PartialEq, Debug, Copy)]
+pub struct ObjFace {
+    pub v: [usize; 3],
+    pub vt: Option<[usize; 3]>,
+    pub vn: Option<[usize; 3]>,
+}
+
+// Faces collected under an `o` or `g` statement.
+pub struct ObjGroup {
+    pub name: String,
+    pub faces: Vec<ObjFace>,
+}
+
+pub struct ObjFile {
+    pub vertices: Vec<Vector3D>,
+    pub vertex_colors: Vec<Color>,
+    pub texcoords: Vec<(f32, f32)>,
+    pub normals: Vec<Vector3D>,
+    pub groups: Vec<ObjGroup>,
+}
+
+impl ObjFile {
+
+    pub fn read(r: &mut Reader) -> MeshResult<ObjFile> {
+        let data = try!(r.read_to_end());
+        let text = match str::from_utf8(&data) {
+            Ok(t) => t,
+            Err(_) => return Err(parse_error(1, "OBJ file is not valid UTF-8".to_string())),
+        };
+        let mut file = ObjFile {
+            vertices: Vec::new(),
+            vertex_colors: Vec::new(),
+            texcoords: Vec::new(),
+            normals: Vec::new(),
+            groups: vec![ObjGroup { name: "default".to_string(), faces: Vec::new() }],
+        };
+        for (i, raw) in text.lines().enumerate() {
+            let line = i + 1;
+            let content = match raw.find('#') {
+                Some(c) => &raw[..c],
+                None => raw,
+            };
+            let words: Vec<&str> = content.split(|c: char| c.is_whitespace())
+                                          .filter(|w| !w.is_empty()).collect();
+            if words.is_empty() {
+                continue;
+            }
+            let args = &words[1..];
+            match words[0] {
+                "v" => {
+                    let xs = try!(numbers(line, args, 3));
+                    file.vertices.push(Vector3D { x: xs[0], y: xs[1], z: xs[2] });
+                    // Vertex colors are a common extension: v x y z r g b
+                    if xs.len() >= 6 {
+                        file.vertex_colors.resize(file.vertices.len() - 1, Color::white());
+                        file.vertex_colors.push(Color::rgb(xs[3], xs[4], xs[5]));
+                    }
+                },
+                "vt" => {
+                    let xs = try!(numbers(line, args, 1));
+                    file.texcoords.push((xs[0], if xs.len() > 1 { xs[1] } else { 0.0 }));
+                },
+                "vn" => {
+                    let xs = try!(numbers(line, args, 3));
+                    file.normals.push(Vector3D { x: xs[0], y: xs[1], z: xs[2] });
+                },
+                "f" => try!(file.read_face(line, args)),
+                "o" | "g" => {
+                    let name = args.connect(" ");
+                    let last = file.groups.len() - 1;
+                    if file.groups[last].faces.is_empty() {
+                        file.groups[last].name = name;
+                    } else {
+                        file.groups.push(ObjGroup { name: name, faces: Vec::new() });
+                    }
+                },
+                // Materials, smoothing groups, curves and the like carry
+                // nothing a Mesh can hold.
+                _ => {},
+            }
+        }
+        if !file.vertex_colors.is_empty() {
+            file.vertex_colors.resize(file.vertices.len(), Color::white());
+        }
+        file.groups.retain(|g| !g.faces.is_empty());
+        Ok(file)
+    }
+
+    // Polygons are triangulated as a fan around their first corner.
+    fn read_face(&mut self, line: usize, args: &[&str]) -> MeshResult<()> {
+        if args.len() < 3 {
+            return Err(parse_error(line, format!("face needs at least 3 vertices, found {}", args.len())));
+        }
+        let mut corners = Vec::with_capacity(args.len());
+        for arg in args.iter() {
+            let mut parts = arg.split('/');
+            let v = try!(index(line, parts.next(), self.vertices.len(), "vertex"));
+            let vt = try!(index(line, parts.next(), self.texcoords.len(), "texture coordinate"));
+            let vn = try!(index(line, parts.next(), self.normals.len(), "normal"));
+            match v {
+                Some(v) => corners.push((v, vt, vn)),
+                None => return Err(parse_error(line, format!("face corner '{}' has no vertex", arg))),
+            }
+        }
+        let group = self.groups.len() - 1;
+        for i in 1..corners.len() - 1 {
+            let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+            self.groups[group].faces.push(ObjFace {
+                v: [a.0, b.0, c.0],
+                vt: match (a.1, b.1, c.1) { (Some(x), Some(y), Some(z)) => Some([x, y, z]), _ => None },
+                vn: match (a.2, b.2, c.2) { (Some(x), Some(y), Some(z)) => Some([x, y, z]), _ => None },
+            });
+        }
+        Ok(())
+    }
+
+    // Face normals (vn) become per-vertex normals. A Mesh holds a single
+    // normal per vertex, so a vertex given two different ones is an error.
+    // Vertices no face gives a normal get the smoothed facet normal.
+    pub fn as_mesh(&self) -> MeshResult<Mesh> {
+        let mut mesh = Mesh::new_from_parts(self.vertices.clone(), Vec::new());
+        let mut normals: Vec<Option<Vector3D>> = vec![None; self.vertices.len()];
+        for g in self.groups.iter() {
+            for f in g.faces.iter() {
+                mesh.facets.push(Facet { v1: f.v[0], v2: f.v[1], v3: f.v[2], n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } });
+                if let Some(vn) = f.vn {
+                    for k in 0..3 {
+                        let n = self.normals[vn[k]];
+                        match normals[f.v[k]] {
+                            Some(old) if old != n => return Err(MeshError::InvalidMesh(
+                                format!("OBJ vertex {} has more than one normal", f.v[k] + 1))),
+                            _ => normals[f.v[k]] = Some(n),
+                        }
+                    }
+                }
+            }
+        }
+        mesh.vertex_colors = self.vertex_colors.clone();
+        mesh.calculate_normals();
+        if normals.iter().any(|n| n.is_some()) {
+            let smooth = mesh.smooth_vertex_normals();
+            mesh.vertex_normals = normals.iter().zip(smooth.iter()).map(|(n, s)| n.unwrap_or(*s)).collect();
+        }
+        Ok(mesh)
+    }
+
+    // Builds a single group holding every facet. Normals are the mesh's
+    // vertex normals when it has them, else one per facet.
+    pub fn from_mesh(m: &Mesh, name: &str) -> ObjFile {
+        let per_vertex = !m.vertex_normals.is_empty();
+        let faces = m.facets.iter().enumerate().map(|(i, f)| ObjFace {
+            v: [f.v1, f.v2, f.v3],
+            vt: None,
+            vn: Some(if per_vertex { [f.v1, f.v2, f.v3] } else { [i, i, i] }),
+        }).collect();
+        ObjFile {
+            vertices: m.vertices.clone(),
+            vertex_colors: m.vertex_colors.clone(),
+            texcoords: Vec::new(),
+            normals: if per_vertex { m.vertex_normals.clone() } else { m.facets.iter().map(|f| f.n).collect() },
+            groups: vec![ObjGroup { name: name.to_string(), faces: faces }],
+        }
+    }
+
+    pub fn write(&self, w: &mut Writer) -> MeshResult<()> {
+        try!(writeln!(w, "# Written by meshman"));
+        for (i, v) in self.vertices.iter().enumerate() {
+            match self.vertex_colors.get(i) {
+                Some(c) => try!(writeln!(w, "v {} {} {} {} {} {}", v.x, v.y, v.z, c.r, c.g, c.b)),
+                None => try!(writeln!(w, "v {} {} {}", v.x, v.y, v.z)),
+            }
+        }
+        for &(u, v) in self.texcoords.iter() {
+            try!(writeln!(w, "vt {} {}", u, v));
+        }
+        for n in self.normals.iter() {
+            try!(writeln!(w, "vn {} {} {}", n.x, n.y, n.z));
+        }
+        for g in self.groups.iter() {
+            try!(writeln!(w, "o {}", g.name));
+            for f in g.faces.iter() {
+                try!(write!(w, "f"));
+                for k in 0..3 {
+                    match (f.vt, f.vn) {
+                        (Some(vt), Some(vn)) => try!(write!(w, " {}/{}/{}", f.v[k] + 1, vt[k] + 1, vn[k] + 1)),
+                        (Some(vt), None) => try!(write!(w, " {}/{}", f.v[k] + 1, vt[k] + 1)),
+                        (None, Some(vn)) => try!(write!(w, " {}//{}", f.v[k] + 1, vn[k] + 1)),
+                        (None, None) => try!(write!(w, " {}", f.v[k] + 1)),
+                    }
+                }
+                try!(writeln!(w, ""));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn numbers(line: usize, args: &[&str], min: usize) -> MeshResult<Vec<f32>> {
+    if args.len() < min {
+        return Err(parse_error(line, format!("expected at least {} numbers, found {}", min, args.len())));
+    }
+    let mut xs = Vec::with_capacity(args.len());
+    for a in args.iter() {
+        match a.parse::<f32>() {
+            Ok(x) => xs.push(x),
+            Err(_) => return Err(parse_error(line, format!("invalid number '{}'", a))),
+        }
+    }
+    Ok(xs)
+}
+
+// Resolves a 1-based (or negative, counting back from the end) index. Empty
+// or missing fields give None.
+fn index(line: usize, field: Option<&str>, count: usize, what: &str) -> MeshResult<Option<usize>> {
+    let field = match field {
+        Some(f) if !f.is_empty() => f,
+        _ => return Ok(None),
+    };
+    let i = match field.parse::<isize>() {
+        Ok(i) => i,
+        Err(_) => return Err(parse_error(line, format!("invalid {} index '{}'", what, field))),
+    };
+    let resolved = if i < 0 { count as isize + i } else { i - 1 };
+    if i == 0 || resolved < 0 || resolved >= count as isize {
+        return Err(parse_error(line, format!("{} index {} out of range ({} defined)", what, i, count)));
+    }
+    Ok(Some(resolved as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::old_io::{BufReader,MemWriter};
+    use parse::parse_error;
+    use vector::Vector3D;
+    use error::MeshError;
+
+    const QUAD_AND_TRI: &'static str = "# test
+o first
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vn 0 0 1
+f 1/1/1 2/1/1 3/1/1 4/1/1
+g second
+v 0 0 1 # trailing comment
+f -1//1 -4//1 -3//1
+";
+
+    #[test]
+    fn reads_polygons_groups_and_negative_indices() {
+        let obj = ObjFile::read(&mut BufReader::new(QUAD_AND_TRI.as_bytes())).unwrap();
+        assert_eq!(obj.groups.len(), 2);
+        assert_eq!(obj.groups[0].name, "first");
+        assert_eq!(obj.groups[0].faces.len(), 2);
+        assert_eq!(obj.groups[0].faces[1].v, [0, 2, 3]);
+        assert_eq!(obj.groups[0].faces[1].vn, Some([0, 0, 0]));
+        assert_eq!(obj.groups[1].faces[0].v, [4, 1, 2]);
+        assert_eq!(obj.groups[1].faces[0].vt, None);
+
+        let mesh = obj.as_mesh().unwrap();
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.facets.len(), 3);
+    }
+
+    #[test]
+    fn bad_index_is_a_parse_error() {
+        let text = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        let err = ObjFile::read(&mut BufReader::new(text.as_bytes())).err().unwrap();
+        assert_eq!(err, parse_error(3, "vertex index 3 out of range (2 defined)".to_string()));
+    }
+
+    #[test]
+    fn mesh_round_trip() {
+        let mesh = ObjFile::read(&mut BufReader::new(QUAD_AND_TRI.as_bytes())).unwrap().as_mesh().unwrap();
+        let mut w = MemWriter::new();
+        ObjFile::from_mesh(&mesh, "part").write(&mut w).unwrap();
+        let data = w.into_inner();
+        let obj = ObjFile::read(&mut BufReader::new(&data)).unwrap();
+        assert_eq!(obj.groups[0].name, "part");
+        assert_eq!(obj.normals.len(), 5);
+        assert_eq!(obj.as_mesh().unwrap(), mesh);
+    }
+
+    #[test]
+    fn face_normals_become_vertex_normals() {
+        let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+                    vn 0 0 -1\nvn 0 -1 0\n\
+                    f 1//1 3//1 2//1\nf 1 2 4\n";
+        let mesh = ObjFile::read(&mut BufReader::new(text.as_bytes())).unwrap().as_mesh().unwrap();
+        let down = Vector3D { x: 0.0, y: 0.0, z: -1.0 };
+        assert_eq!(mesh.vertex_normals.len(), 4);
+        assert_eq!(&mesh.vertex_normals[..3], &[down, down, down]);
+        // The apex only touches the face without normals.
+        assert_eq!(mesh.vertex_normals[3], Vector3D { x: 0.0, y: -1.0, z: 0.0 });
+
+        let clash = text.replace("f 1 2 4", "f 1//2 2//2 4//2");
+        let obj = ObjFile::read(&mut BufReader::new(clash.as_bytes())).unwrap();
+        assert_eq!(obj.as_mesh().err().unwrap(),
+                   MeshError::InvalidMesh("OBJ vertex 1 has more than one normal".to_string()));
+    }
+}
@@ -0,0 +1,209 @@
+// OFF / COFF (Object File Format) handling.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::old_io::{Reader,Writer};
+use std::str;
+use mesh::{Color,Facet,Mesh};
+use vector::Vector3D;
+use parse::parse_error;
+use error::MeshResult;
+
+pub struct OffFile;
+
+impl OffFile {
+
+    // Reads OFF, or COFF with per-vertex colors. Faces may be any polygon and
+    // may carry a color after their indices.
+    pub fn read(r: &mut Reader) -> MeshResult<Mesh> {
+        let data = try!(r.read_to_end());
+        let text = match str::from_utf8(&data) {
+            Ok(t) => t,
+            Err(_) => return Err(parse_error(1, "OFF file is not valid UTF-8".to_string())),
+        };
+        // Non-blank lines with comments stripped, paired with line numbers.
+        let mut lines = text.lines().enumerate().filter_map(|(i, l)| {
+            let l = match l.find('#') {
+                Some(c) => &l[..c],
+                None => l,
+            };
+            let words: Vec<&str> = l.split(|c: char| c.is_whitespace()).filter(|w| !w.is_empty()).collect();
+            if words.is_empty() { None } else { Some((i + 1, words)) }
+        });
+
+        let (line, mut header) = match lines.next() {
+            Some(h) => h,
+            None => return Err(parse_error(1, "empty OFF file".to_string())),
+        };
+        let colored = match header[0] {
+            "OFF" => false,
+            "COFF" => true,
+            k => return Err(parse_error(line, format!("expected OFF or COFF, found '{}'", k))),
+        };
+        // The counts may follow the keyword on the same line.
+        let (line, counts) = if header.len() > 1 {
+            header.remove(0);
+            (line, header)
+        } else {
+            match lines.next() {
+                Some(c) => c,
+                None => return Err(parse_error(line, "missing vertex and face counts".to_string())),
+            }
+        };
+        if counts.len() < 2 {
+            return Err(parse_error(line, "expected vertex and face counts".to_string()));
+        }
+        let nv = try!(count(line, counts[0]));
+        let nf = try!(count(line, counts[1]));
+
+        let mut mesh = Mesh::new();
+        for _ in 0..nv {
+            let (line, words) = match lines.next() {
+                Some(l) => l,
+                None => return Err(parse_error(line, format!("expected {} vertices", nv))),
+            };
+            let xs = try!(numbers(line, &words));
+            if xs.len() < 3 {
+                return Err(parse_error(line, "vertex needs x, y and z".to_string()));
+            }
+            mesh.vertices.push(Vector3D { x: xs[0], y: xs[1], z: xs[2] });
+            if colored {
+                mesh.vertex_colors.push(try!(color(line, &words[3..])));
+            }
+        }
+
+        let mut colors = Vec::new();
+        for _ in 0..nf {
+            let (line, words) = match lines.next() {
+                Some(l) => l,
+                None => return Err(parse_error(line, format!("expected {} faces", nf))),
+            };
+            let n = try!(count(line, words[0]));
+            if n < 3 || words.len() < n + 1 {
+                return Err(parse_error(line, format!("face needs at least 3 of its {} indices", n)));
+            }
+            let mut ids = Vec::with_capacity(n);
+            for w in words[1..n + 1].iter() {
+                let i = try!(count(line, *w));
+                if i >= nv {
+                    return Err(parse_error(line, format!("vertex index {} out of range ({} vertices)", i, nv)));
+                }
+                ids.push(i);
+            }
+            let face_color = if words.len() > n + 1 { Some(try!(color(line, &words[n + 1..]))) } else { None };
+            // Polygons are triangulated as a fan around their first corner.
+            for k in 1..n - 1 {
+                mesh.facets.push(Facet { v1: ids[0], v2: ids[k], v3: ids[k + 1], n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } });
+                colors.push(face_color);
+            }
+        }
+        if colors.iter().any(|c: &Option<Color>| c.is_some()) {
+            mesh.facet_colors = colors.iter().map(|c| c.unwrap_or(Color::white())).collect();
+        }
+        mesh.calculate_normals();
+        Ok(mesh)
+    }
+
+    // Writes COFF when the mesh has vertex colors, OFF otherwise; facet
+    // colors follow each face's indices. Colors are written as 0..255.
+    pub fn write(m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        try!(m.check_indices());
+        let colored = !m.vertex_colors.is_empty();
+        try!(writeln!(w, "{}", if colored { "COFF" } else { "OFF" }));
+        try!(writeln!(w, "{} {} 0", m.vertices.len(), m.facets.len()));
+        for (i, v) in m.vertices.iter().enumerate() {
+            if colored {
+                let c = m.vertex_colors[i].to_bytes();
+                try!(writeln!(w, "{} {} {} {} {} {} {}", v.x, v.y, v.z, c[0], c[1], c[2], c[3]));
+            } else {
+                try!(writeln!(w, "{} {} {}", v.x, v.y, v.z));
+            }
+        }
+        for (i, f) in m.facets.iter().enumerate() {
+            match m.facet_colors.get(i) {
+                Some(c) => {
+                    let c = c.to_bytes();
+                    try!(writeln!(w, "3 {} {} {} {} {} {} {}", f.v1, f.v2, f.v3, c[0], c[1], c[2], c[3]));
+                },
+                None => try!(writeln!(w, "3 {} {} {}", f.v1, f.v2, f.v3)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn count(line: usize, word: &str) -> MeshResult<usize> {
+    match word.parse::<usize>() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(parse_error(line, format!("invalid count or index '{}'", word))),
+    }
+}
+
+fn numbers(line: usize, words: &[&str]) -> MeshResult<Vec<f32>> {
+    let mut xs = Vec::with_capacity(words.len());
+    for w in words.iter() {
+        match w.parse::<f32>() {
+            Ok(x) => xs.push(x),
+            Err(_) => return Err(parse_error(line, format!("invalid number '{}'", w))),
+        }
+    }
+    Ok(xs)
+}
+
+// Three or four components, either all integers in 0..255 or floats in 0..1.
+fn color(line: usize, words: &[&str]) -> MeshResult<Color> {
+    if words.len() != 3 && words.len() != 4 {
+        return Err(parse_error(line, format!("expected an RGB or RGBA color, found {} values", words.len())));
+    }
+    let mut c = try!(numbers(line, words));
+    if words.iter().all(|w| !w.contains('.')) {
+        for x in c.iter_mut() {
+            *x /= 255.0;
+        }
+    }
+    Ok(Color { r: c[0], g: c[1], b: c[2], a: if c.len() == 4 { c[3] } else { 1.0 } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::Color;
+    use std::old_io::{BufReader,MemWriter};
+    use parse::parse_error;
+
+    const COFF: &'static str = "COFF
+# a colored square
+4 1 0
+0 0 0  255 0 0
+1 0 0  255 0 0 255
+1 1 0  0.0 0.0 1.0
+0 1 0  0 0 255
+4  0 1 2 3  0 255 0
+";
+
+    #[test]
+    fn reads_coff_polygons_and_colors() {
+        let mesh = OffFile::read(&mut BufReader::new(COFF.as_bytes())).unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.facets.len(), 2);
+        assert_eq!(mesh.vertex_colors[0], Color::rgb(1.0, 0.0, 0.0));
+        assert_eq!(mesh.vertex_colors[2], Color::rgb(0.0, 0.0, 1.0));
+        assert_eq!(mesh.facet_colors, vec![Color::rgb(0.0, 1.0, 0.0); 2]);
+    }
+
+    #[test]
+    fn bad_index_is_a_parse_error() {
+        let text = "OFF 3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 3\n";
+        let err = OffFile::read(&mut BufReader::new(text.as_bytes())).err().unwrap();
+        assert_eq!(err, parse_error(5, "vertex index 3 out of range (3 vertices)".to_string()));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mesh = OffFile::read(&mut BufReader::new(COFF.as_bytes())).unwrap();
+        let mut w = MemWriter::new();
+        OffFile::write(&mesh, &mut w).unwrap();
+        let data = w.into_inner();
+        assert_eq!(OffFile::read(&mut BufReader::new(&data)).unwrap(), mesh);
+    }
+}
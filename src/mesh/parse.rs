@@ -0,0 +1,106 @@
+// Shared helpers for the text based readers.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use error::{MeshError,MeshResult};
+
+// Splits a text buffer into whitespace separated tokens, keeping track of the
+// line each token came from so errors can point at it.
+#[derive(Clone)]
+pub struct Tokens<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(src: &'a str) -> Tokens<'a> {
+        Tokens { src: src, pos: 0, line: 1 }
+    }
+
+    // Line number of the most recently returned token.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn peek(&self) -> Option<&'a str> {
+        self.clone().next()
+    }
+
+    // Returns the next token only if it is on the same line as the last one.
+    pub fn next_on_line(&mut self) -> Option<&'a str> {
+        let line = self.line;
+        let mut ahead = self.clone();
+        match ahead.next() {
+            Some(t) if ahead.line == line => { *self = ahead; Some(t) },
+            _ => None,
+        }
+    }
+
+    pub fn expect(&mut self, word: &str) -> MeshResult<()> {
+        match self.next() {
+            Some(t) if t == word => Ok(()),
+            Some(t) => Err(parse_error(self.line, format!("expected '{}', found '{}'", word, t))),
+            None => Err(parse_error(self.line, format!("expected '{}', found end of file", word))),
+        }
+    }
+
+    pub fn next_f32(&mut self) -> MeshResult<f32> {
+        match self.next() {
+            Some(t) => match t.parse::<f32>() {
+                Ok(v) => Ok(v),
+                Err(_) => Err(parse_error(self.line, format!("invalid number '{}'", t))),
+            },
+            None => Err(parse_error(self.line, "expected a number, found end of file".to_string())),
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let bytes = self.src.as_bytes();
+        while self.pos < bytes.len() && is_space(bytes[self.pos]) {
+            if bytes[self.pos] == b'\n' {
+                self.line += 1;
+            }
+            self.pos += 1;
+        }
+        if self.pos == bytes.len() {
+            return None;
+        }
+        let start = self.pos;
+        while self.pos < bytes.len() && !is_space(bytes[self.pos]) {
+            self.pos += 1;
+        }
+        Some(&self.src[start..self.pos])
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\r' || b == b'\n'
+}
+
+pub fn parse_error(line: usize, msg: String) -> MeshError {
+    MeshError::Parse { line: line, msg: msg }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_track_lines() {
+        let mut t = Tokens::new("solid a\n  facet normal\n\n 1e+001");
+        assert_eq!(t.next(), Some("solid"));
+        assert_eq!(t.next_on_line(), Some("a"));
+        assert_eq!(t.next_on_line(), None);
+        assert_eq!(t.next(), Some("facet"));
+        assert_eq!(t.line(), 2);
+        t.expect("normal").unwrap();
+        assert_eq!(t.next_f32().unwrap(), 10.0);
+        assert_eq!(t.line(), 4);
+        assert_eq!(t.next(), None);
+    }
+}
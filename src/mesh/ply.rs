@@ -0,0 +1,570 @@
+// PLY (Stanford polygon) file handling.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::old_io::{BufReader,Reader,Writer};
+use std::num::Float;
+use std::str;
+use std::usize;
+use mesh::{Color,Facet,Mesh};
+use vector::Vector3D;
+use parse::{Tokens,parse_error};
+use error::{MeshError,MeshResult};
+
+#[derive(PartialEq, Eq, Debug, Copy)]
+pub enum PlyEncoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(PartialEq, Eq, Debug, Copy)]
+pub enum PlyType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl PlyType {
+    fn from_name(name: &str) -> Option<PlyType> {
+        match name {
+            "char" | "int8" => Some(PlyType::Char),
+            "uchar" | "uint8" => Some(PlyType::UChar),
+            "short" | "int16" => Some(PlyType::Short),
+            "ushort" | "uint16" => Some(PlyType::UShort),
+            "int" | "int32" => Some(PlyType::Int),
+            "uint" | "uint32" => Some(PlyType::UInt),
+            "float" | "float32" => Some(PlyType::Float),
+            "double" | "float64" => Some(PlyType::Double),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            PlyType::Char => "char",
+            PlyType::UChar => "uchar",
+            PlyType::Short => "short",
+            PlyType::UShort => "ushort",
+            PlyType::Int => "int",
+            PlyType::UInt => "uint",
+            PlyType::Float => "float",
+            PlyType::Double => "double",
+        }
+    }
+}
+
+// A property is a single scalar, or a list when it has a count type.
+#[derive(PartialEq, Eq, Debug)]
+pub struct PlyProperty {
+    pub name: String,
+    pub ty: PlyType,
+    pub count: Option<PlyType>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum PlyValue {
+    Scalar(f64),
+    List(Vec<f64>),
+}
+
+// Rows hold one value per property, in header order.
+pub struct PlyElement {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<PlyProperty>,
+    pub rows: Vec<Vec<PlyValue>>,
+}
+
+impl PlyElement {
+    pub fn property(&self, name: &str) -> Option<usize> {
+        self.properties.iter().position(|p| p.name == name)
+    }
+}
+
+pub struct PlyFile {
+    pub encoding: PlyEncoding,
+    pub comments: Vec<String>,
+    pub elements: Vec<PlyElement>,
+}
+
+impl PlyFile {
+
+    pub fn read(r: &mut Reader) -> MeshResult<PlyFile> {
+        let data = try!(r.read_to_end());
+        let end = match find(&data, b"end_header") {
+            Some(i) => i,
+            None => return Err(parse_error(1, "missing end_header".to_string())),
+        };
+        let header = match str::from_utf8(&data[..end]) {
+            Ok(h) => h,
+            Err(_) => return Err(parse_error(1, "PLY header is not valid text".to_string())),
+        };
+        let mut file = try!(PlyFile::read_header(header));
+        let header_lines = header.lines().count();
+
+        // The body starts after the line holding end_header.
+        let mut body = end + b"end_header".len();
+        while body < data.len() && data[body] != b'\n' {
+            body += 1;
+        }
+        let body = &data[::std::cmp::min(body + 1, data.len())..];
+
+        match file.encoding {
+            PlyEncoding::Ascii => {
+                let text = match str::from_utf8(body) {
+                    Ok(t) => t,
+                    Err(_) => return Err(parse_error(header_lines + 1, "ASCII PLY body is not valid text".to_string())),
+                };
+                try!(file.read_ascii(text, header_lines + 1));
+            },
+            _ => try!(file.read_binary(body)),
+        }
+        Ok(file)
+    }
+
+    fn read_header(header: &str) -> MeshResult<PlyFile> {
+        let mut file = PlyFile { encoding: PlyEncoding::Ascii, comments: Vec::new(), elements: Vec::new() };
+        let mut lines = header.lines().enumerate();
+        match lines.next() {
+            Some((_, l)) if l.trim() == "ply" => {},
+            _ => return Err(parse_error(1, "missing 'ply' magic".to_string())),
+        }
+        let mut has_format = false;
+        for (i, l) in lines {
+            let line = i + 1;
+            let words: Vec<&str> = l.split(|c: char| c.is_whitespace()).filter(|w| !w.is_empty()).collect();
+            if words.is_empty() {
+                continue;
+            }
+            match words[0] {
+                "format" => {
+                    file.encoding = match words.get(1) {
+                        Some(&"ascii") => PlyEncoding::Ascii,
+                        Some(&"binary_little_endian") => PlyEncoding::BinaryLittleEndian,
+                        Some(&"binary_big_endian") => PlyEncoding::BinaryBigEndian,
+                        _ => return Err(parse_error(line, format!("unknown format '{}'", l.trim()))),
+                    };
+                    has_format = true;
+                },
+                "comment" | "obj_info" => file.comments.push(words[1..].connect(" ")),
+                "element" => {
+                    if words.len() != 3 {
+                        return Err(parse_error(line, "element needs a name and a count".to_string()));
+                    }
+                    let count = match words[2].parse::<usize>() {
+                        Ok(c) => c,
+                        Err(_) => return Err(parse_error(line, format!("invalid element count '{}'", words[2]))),
+                    };
+                    file.elements.push(PlyElement {
+                        name: words[1].to_string(), count: count, properties: Vec::new(), rows: Vec::new(),
+                    });
+                },
+                "property" => {
+                    let property = if words.len() == 3 {
+                        PlyProperty { name: words[2].to_string(), ty: try!(ply_type(line, words[1])), count: None }
+                    } else if words.len() == 5 && words[1] == "list" {
+                        PlyProperty {
+                            name: words[4].to_string(), ty: try!(ply_type(line, words[3])),
+                            count: Some(try!(ply_type(line, words[2]))),
+                        }
+                    } else {
+                        return Err(parse_error(line, format!("malformed property '{}'", l.trim())));
+                    };
+                    match file.elements.last_mut() {
+                        Some(e) => e.properties.push(property),
+                        None => return Err(parse_error(line, "property before any element".to_string())),
+                    }
+                },
+                _ => return Err(parse_error(line, format!("unknown header keyword '{}'", words[0]))),
+            }
+        }
+        if !has_format {
+            return Err(parse_error(1, "missing format line".to_string()));
+        }
+        Ok(file)
+    }
+
+    // `offset` is the number of lines before the body, for error reporting.
+    fn read_ascii(&mut self, text: &str, offset: usize) -> MeshResult<()> {
+        let mut tokens = Tokens::new(text);
+        let number = |tokens: &mut Tokens| -> MeshResult<f64> {
+            match tokens.next() {
+                Some(t) => match t.parse::<f64>() {
+                    Ok(v) => Ok(v),
+                    Err(_) => Err(parse_error(offset + tokens.line(), format!("invalid number '{}'", t))),
+                },
+                None => Err(parse_error(offset + tokens.line(), "unexpected end of data".to_string())),
+            }
+        };
+        for e in self.elements.iter_mut() {
+            for _ in 0..e.count {
+                let mut row = Vec::with_capacity(e.properties.len());
+                for p in e.properties.iter() {
+                    match p.count {
+                        None => row.push(PlyValue::Scalar(try!(number(&mut tokens)))),
+                        Some(_) => {
+                            let n = try!(number(&mut tokens));
+                            let n = try!(whole(n, offset + tokens.line(), "list length"));
+                            let mut items = Vec::new();
+                            for _ in 0..n {
+                                items.push(try!(number(&mut tokens)));
+                            }
+                            row.push(PlyValue::List(items));
+                        },
+                    }
+                }
+                e.rows.push(row);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_binary(&mut self, body: &[u8]) -> MeshResult<()> {
+        let big = self.encoding == PlyEncoding::BinaryBigEndian;
+        let r = &mut BufReader::new(body);
+        for e in self.elements.iter_mut() {
+            for _ in 0..e.count {
+                let mut row = Vec::with_capacity(e.properties.len());
+                for p in e.properties.iter() {
+                    match p.count {
+                        None => row.push(PlyValue::Scalar(try!(read_value(r, p.ty, big)))),
+                        Some(count_ty) => {
+                            let n = try!(whole(try!(read_value(r, count_ty, big)), 0, "list length"));
+                            let mut items = Vec::new();
+                            for _ in 0..n {
+                                items.push(try!(read_value(r, p.ty, big)));
+                            }
+                            row.push(PlyValue::List(items));
+                        },
+                    }
+                }
+                e.rows.push(row);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn element(&self, name: &str) -> Option<&PlyElement> {
+        self.elements.iter().find(|e| e.name == name)
+    }
+
+    // Maps the "vertex" and "face" elements onto a mesh, picking up normals
+    // (nx, ny, nz) and colors (red, green, blue, alpha) where declared.
+    pub fn as_mesh(&self) -> MeshResult<Mesh> {
+        let mut mesh = Mesh::new();
+        let vertex = match self.element("vertex") {
+            Some(v) => v,
+            None => return Ok(mesh),
+        };
+        let xyz = match (vertex.property("x"), vertex.property("y"), vertex.property("z")) {
+            (Some(x), Some(y), Some(z)) => [x, y, z],
+            _ => return Err(parse_error(1, "vertex element needs x, y and z".to_string())),
+        };
+        let normal = match (vertex.property("nx"), vertex.property("ny"), vertex.property("nz")) {
+            (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+            _ => None,
+        };
+        let vertex_color = color_properties(vertex);
+        for row in vertex.rows.iter() {
+            mesh.vertices.push(vector(row, xyz));
+            if let Some(n) = normal {
+                mesh.vertex_normals.push(vector(row, n));
+            }
+            if let Some(c) = vertex_color {
+                mesh.vertex_colors.push(color(vertex, row, c));
+            }
+        }
+
+        if let Some(face) = self.element("face") {
+            let indices = match face.property("vertex_indices").or(face.property("vertex_index")) {
+                Some(i) => i,
+                None => return Err(parse_error(1, "face element needs vertex_indices".to_string())),
+            };
+            let face_color = color_properties(face);
+            let mut colors = Vec::new();
+            for (n, row) in face.rows.iter().enumerate() {
+                let list = match row[indices] {
+                    PlyValue::List(ref l) => l,
+                    PlyValue::Scalar(_) => return Err(parse_error(1, "vertex_indices must be a list".to_string())),
+                };
+                let mut ids = Vec::with_capacity(list.len());
+                for i in list.iter() {
+                    let i = try!(whole(*i, 1, "vertex index"));
+                    if i >= mesh.vertices.len() {
+                        return Err(MeshError::InvalidIndex { facet: n, index: i, vertices: mesh.vertices.len() });
+                    }
+                    ids.push(i);
+                }
+                // Polygons are triangulated as a fan around their first corner.
+                for k in 1..ids.len().saturating_sub(1) {
+                    mesh.facets.push(Facet { v1: ids[0], v2: ids[k], v3: ids[k + 1], n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } });
+                    if let Some(c) = face_color {
+                        colors.push(color(face, row, c));
+                    }
+                }
+            }
+            mesh.facet_colors = colors;
+        }
+        mesh.calculate_normals();
+        Ok(mesh)
+    }
+
+    pub fn write(m: &Mesh, w: &mut Writer, encoding: PlyEncoding) -> MeshResult<()> {
+        let normals = !m.vertex_normals.is_empty();
+        let vertex_colors = !m.vertex_colors.is_empty();
+        let facet_colors = !m.facet_colors.is_empty();
+
+        try!(m.check_indices());
+        try!(writeln!(w, "ply"));
+        try!(writeln!(w, "format {} 1.0", match encoding {
+            PlyEncoding::Ascii => "ascii",
+            PlyEncoding::BinaryLittleEndian => "binary_little_endian",
+            PlyEncoding::BinaryBigEndian => "binary_big_endian",
+        }));
+        try!(writeln!(w, "comment Written by meshman"));
+        try!(writeln!(w, "element vertex {}", m.vertices.len()));
+        for p in ["x", "y", "z"].iter() {
+            try!(writeln!(w, "property float {}", p));
+        }
+        if normals {
+            for p in ["nx", "ny", "nz"].iter() {
+                try!(writeln!(w, "property float {}", p));
+            }
+        }
+        if vertex_colors {
+            for p in ["red", "green", "blue", "alpha"].iter() {
+                try!(writeln!(w, "property uchar {}", p));
+            }
+        }
+        try!(writeln!(w, "element face {}", m.facets.len()));
+        try!(writeln!(w, "property list uchar int vertex_indices"));
+        if facet_colors {
+            for p in ["red", "green", "blue", "alpha"].iter() {
+                try!(writeln!(w, "property uchar {}", p));
+            }
+        }
+        try!(writeln!(w, "end_header"));
+
+        let mut row: Vec<(PlyType, f64)> = Vec::new();
+        for (i, v) in m.vertices.iter().enumerate() {
+            row.clear();
+            row.push_all(&[(PlyType::Float, v.x as f64), (PlyType::Float, v.y as f64), (PlyType::Float, v.z as f64)]);
+            if normals {
+                let n = m.vertex_normals[i];
+                row.push_all(&[(PlyType::Float, n.x as f64), (PlyType::Float, n.y as f64), (PlyType::Float, n.z as f64)]);
+            }
+            if vertex_colors {
+                for b in m.vertex_colors[i].to_bytes().iter() {
+                    row.push((PlyType::UChar, *b as f64));
+                }
+            }
+            try!(write_row(w, encoding, &row));
+        }
+        for (i, f) in m.facets.iter().enumerate() {
+            row.clear();
+            row.push_all(&[(PlyType::UChar, 3.0), (PlyType::Int, f.v1 as f64),
+                           (PlyType::Int, f.v2 as f64), (PlyType::Int, f.v3 as f64)]);
+            if facet_colors {
+                for b in m.facet_colors[i].to_bytes().iter() {
+                    row.push((PlyType::UChar, *b as f64));
+                }
+            }
+            try!(write_row(w, encoding, &row));
+        }
+        Ok(())
+    }
+}
+
+fn ply_type(line: usize, name: &str) -> MeshResult<PlyType> {
+    match PlyType::from_name(name) {
+        Some(t) => Ok(t),
+        None => Err(parse_error(line, format!("unknown property type '{}'", name))),
+    }
+}
+
+// Counts and indices are stored as numbers of any type, so they are checked
+// to be whole and not negative. Lists are not allocated up front, as a
+// corrupt count could ask for more memory than there is.
+fn whole(v: f64, line: usize, what: &str) -> MeshResult<usize> {
+    if v >= 0.0 && v.floor() == v && v <= usize::MAX as f64 {
+        Ok(v as usize)
+    } else {
+        Err(parse_error(line, format!("invalid {} '{}'", what, v)))
+    }
+}
+
+fn read_value(r: &mut Reader, ty: PlyType, big: bool) -> MeshResult<f64> {
+    Ok(match (ty, big) {
+        (PlyType::Char, _) => try!(r.read_i8()) as f64,
+        (PlyType::UChar, _) => try!(r.read_u8()) as f64,
+        (PlyType::Short, false) => try!(r.read_le_i16()) as f64,
+        (PlyType::Short, true) => try!(r.read_be_i16()) as f64,
+        (PlyType::UShort, false) => try!(r.read_le_u16()) as f64,
+        (PlyType::UShort, true) => try!(r.read_be_u16()) as f64,
+        (PlyType::Int, false) => try!(r.read_le_i32()) as f64,
+        (PlyType::Int, true) => try!(r.read_be_i32()) as f64,
+        (PlyType::UInt, false) => try!(r.read_le_u32()) as f64,
+        (PlyType::UInt, true) => try!(r.read_be_u32()) as f64,
+        (PlyType::Float, false) => try!(r.read_le_f32()) as f64,
+        (PlyType::Float, true) => try!(r.read_be_f32()) as f64,
+        (PlyType::Double, false) => try!(r.read_le_f64()),
+        (PlyType::Double, true) => try!(r.read_be_f64()),
+    })
+}
+
+fn write_row(w: &mut Writer, encoding: PlyEncoding, row: &[(PlyType, f64)]) -> MeshResult<()> {
+    if encoding == PlyEncoding::Ascii {
+        let words: Vec<String> = row.iter().map(|&(ty, v)| match ty {
+            PlyType::Float | PlyType::Double => format!("{}", v as f32),
+            _ => format!("{}", v as i64),
+        }).collect();
+        try!(writeln!(w, "{}", words.connect(" ")));
+        return Ok(());
+    }
+    let big = encoding == PlyEncoding::BinaryBigEndian;
+    for &(ty, v) in row.iter() {
+        try!(match (ty, big) {
+            (PlyType::Char, _) => w.write_i8(v as i8),
+            (PlyType::UChar, _) => w.write_u8(v as u8),
+            (PlyType::Short, false) => w.write_le_i16(v as i16),
+            (PlyType::Short, true) => w.write_be_i16(v as i16),
+            (PlyType::UShort, false) => w.write_le_u16(v as u16),
+            (PlyType::UShort, true) => w.write_be_u16(v as u16),
+            (PlyType::Int, false) => w.write_le_i32(v as i32),
+            (PlyType::Int, true) => w.write_be_i32(v as i32),
+            (PlyType::UInt, false) => w.write_le_u32(v as u32),
+            (PlyType::UInt, true) => w.write_be_u32(v as u32),
+            (PlyType::Float, false) => w.write_le_f32(v as f32),
+            (PlyType::Float, true) => w.write_be_f32(v as f32),
+            (PlyType::Double, false) => w.write_le_f64(v),
+            (PlyType::Double, true) => w.write_be_f64(v),
+        });
+    }
+    Ok(())
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    if data.len() < needle.len() {
+        return None;
+    }
+    (0..data.len() - needle.len() + 1).find(|i| &data[*i..*i + needle.len()] == needle)
+}
+
+fn scalar(row: &[PlyValue], i: usize) -> f64 {
+    match row[i] {
+        PlyValue::Scalar(v) => v,
+        PlyValue::List(ref l) => if l.is_empty() { 0.0 } else { l[0] },
+    }
+}
+
+fn vector(row: &[PlyValue], i: [usize; 3]) -> Vector3D {
+    Vector3D { x: scalar(row, i[0]) as f32, y: scalar(row, i[1]) as f32, z: scalar(row, i[2]) as f32 }
+}
+
+// Indices of red, green, blue and (optional) alpha.
+fn color_properties(e: &PlyElement) -> Option<[Option<usize>; 4]> {
+    match (e.property("red"), e.property("green"), e.property("blue")) {
+        (Some(r), Some(g), Some(b)) => Some([Some(r), Some(g), Some(b), e.property("alpha")]),
+        _ => None,
+    }
+}
+
+// Integer channels run 0..255, float channels 0..1.
+fn color(e: &PlyElement, row: &[PlyValue], c: [Option<usize>; 4]) -> Color {
+    let channel = |i: Option<usize>| match i {
+        Some(i) => match e.properties[i].ty {
+            PlyType::Float | PlyType::Double => scalar(row, i) as f32,
+            _ => scalar(row, i) as f32 / 255.0,
+        },
+        None => 1.0,
+    };
+    Color { r: channel(c[0]), g: channel(c[1]), b: channel(c[2]), a: channel(c[3]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::Color;
+    use std::old_io::{BufReader,MemWriter};
+    use parse::parse_error;
+
+    const SQUARE: &'static str = "ply
+format ascii 1.0
+comment scanner output
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 255 0 0
+1 1 0 0 0 255
+0 1 0 0 0 255
+4 0 1 2 3
+";
+
+    #[test]
+    fn reads_ascii_with_colors() {
+        let ply = PlyFile::read(&mut BufReader::new(SQUARE.as_bytes())).unwrap();
+        assert_eq!(ply.encoding, PlyEncoding::Ascii);
+        assert_eq!(ply.comments, vec!["scanner output".to_string()]);
+        assert_eq!(ply.element("face").unwrap().rows[0][0], PlyValue::List(vec![0.0, 1.0, 2.0, 3.0]));
+
+        let mesh = ply.as_mesh().unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.facets.len(), 2);
+        assert_eq!(mesh.vertex_colors[2], Color::rgb(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn ascii_errors_report_line() {
+        let text = SQUARE.replace("1 1 0 0 0 255", "1 one 0 0 0 255");
+        let err = PlyFile::read(&mut BufReader::new(text.as_bytes())).err().unwrap();
+        assert_eq!(err, parse_error(16, "invalid number 'one'".to_string()));
+    }
+
+    #[test]
+    fn bad_counts_and_indices_are_rejected() {
+        let text = SQUARE.replace("4 0 1 2 3", "4294967295 0 1 2 3");
+        assert!(PlyFile::read(&mut BufReader::new(text.as_bytes())).is_err());
+        let text = SQUARE.replace("4 0 1 2 3", "-1 0 1 2 3");
+        let err = PlyFile::read(&mut BufReader::new(text.as_bytes())).err().unwrap();
+        assert_eq!(err, parse_error(18, "invalid list length '-1'".to_string()));
+        for index in ["-1", "1.5"].iter() {
+            let text = SQUARE.replace("4 0 1 2 3", &format!("4 0 {} 2 3", index));
+            let ply = PlyFile::read(&mut BufReader::new(text.as_bytes())).unwrap();
+            assert!(ply.as_mesh().is_err());
+        }
+
+        let header = "ply\nformat binary_little_endian 1.0\nelement face 1\n\
+                      property list uint int vertex_indices\nend_header\n";
+        let mut data = header.as_bytes().to_vec();
+        data.push_all(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        assert!(PlyFile::read(&mut BufReader::new(&data)).is_err());
+    }
+
+    #[test]
+    fn binary_round_trips() {
+        let mut mesh = PlyFile::read(&mut BufReader::new(SQUARE.as_bytes())).unwrap().as_mesh().unwrap();
+        mesh.vertex_normals = mesh.vertices.iter().map(|_| mesh.facets[0].n).collect();
+        for encoding in [PlyEncoding::BinaryLittleEndian, PlyEncoding::BinaryBigEndian, PlyEncoding::Ascii].iter() {
+            let mut w = MemWriter::new();
+            PlyFile::write(&mesh, &mut w, *encoding).unwrap();
+            let data = w.into_inner();
+            let ply = PlyFile::read(&mut BufReader::new(&data)).unwrap();
+            assert_eq!(ply.encoding, *encoding);
+            assert_eq!(ply.as_mesh().unwrap(), mesh);
+        }
+    }
+}
@@ -1,51 +1,64 @@
 
-use std::old_io::{File,IoResult,Reader,Writer};
+use std::old_io::{File,Reader,Writer};
+use std::old_io::stdio;
 use std::old_path::Path;
 use Mesh;
 use mesh::Facet;
 use vector::Vector3D;
+use error::{MeshError,MeshResult};
 
 pub struct POV;
 
 impl POV {
-    pub fn write(mesh: &Mesh, file_name: &str) -> IoResult<()> {
-        let out_file_name = file_name.replace("stl", "inc");
-        let path = Path::new(out_file_name.clone());
+    // Writes the scene to `scene_path` and the mesh it includes next to it,
+    // with the same name and an .inc extension.
+    pub fn write(mesh: &Mesh, scene_path: &Path, source: &str) -> MeshResult<()> {
+        let inc_path = scene_path.with_extension("inc");
 
-        let mut file = match File::create(&path) {
-            Err(why) => { return Err(why); },
+        let mut file = match File::create(&inc_path) {
+            Err(why) => { return Err(MeshError::Io(why)); },
             Ok(file) => file
         };
 
         //let modelname = path.file_name();
         let modelname = "m_model";
 
-        try!(file.write_str(&format!("// Source file: {}\n", file_name)));
-        try!(file.write_str(&format!("# declare {} = mesh {{\n", modelname)));
+        try!(POV::write_mesh(mesh, &mut file, source, modelname));
 
-        for facet in mesh.facets.iter() {
-            match file.write_str(&POV::facet_to_povstring(&mesh, facet)) {
-                Err(why) => { return Err(why); },
-                Ok(_) => print!(".")
-            }
-        }
-        println!("done.");
+        let mut modelfile = match File::create(scene_path) {
+            Err(why) => { return Err(MeshError::Io(why)); },
+            Ok(file) => file
+        };
 
-        try!(file.write_str("}\n"));
+        let include = inc_path.filename_str().unwrap_or("model.inc");
+        POV::write_scene(&mut modelfile, include, modelname)
+    }
 
-        let template = POV::read_template();
-        let first_pass = template.replace("FILE_NAME", &out_file_name);
+    // Writes the scene from the template, including the mesh from `include`.
+    pub fn write_scene(w: &mut Writer, include: &str, modelname: &str) -> MeshResult<()> {
+        let template = try!(POV::read_template());
+        let first_pass = template.replace("FILE_NAME", include);
         let second_pass = first_pass.replace("MODEL_NAME", modelname);
+        Ok(try!(w.write_str(&second_pass)))
+    }
 
-        let modelfilename = file_name.replace("stl", "pov");
-        let modelpath = Path::new(modelfilename);
-        let mut modelfile = match File::create(&modelpath) {
-            Err(why) => { return Err(why); },
-            Ok(file) => file
-        };
+    // Writes just the mesh declaration, the part that goes in the .inc file.
+    pub fn write_mesh(mesh: &Mesh, w: &mut Writer, source: &str, modelname: &str) -> MeshResult<()> {
+        try!(mesh.check_indices());
+        try!(w.write_str(&format!("// Source file: {}\n", source)));
+        try!(w.write_str(&format!("# declare {} = mesh {{\n", modelname)));
+
+        // Progress goes to stderr, so that the mesh itself can go to stdout.
+        let mut progress = stdio::stderr();
+        for facet in mesh.facets.iter() {
+            match w.write_str(&POV::facet_to_povstring(&mesh, facet)) {
+                Err(why) => { return Err(MeshError::Io(why)); },
+                Ok(_) => { let _ = progress.write_str("."); }
+            }
+        }
+        let _ = progress.write_str("done.\n");
 
-        try!(modelfile.write_str(&second_pass));
-        Ok(())
+        Ok(try!(w.write_str("}\n")))
     }
 
     fn facet_to_povstring(mesh: &Mesh, facet: &Facet) -> String {
@@ -63,7 +76,7 @@ impl POV {
         format!("    <{}, {}, {}>", vector.y, vector.x, vector.z)
     }
 
-    fn read_template() -> String {
-        File::open(&Path::new("templates/model.pov")).read_to_string().unwrap()
+    fn read_template() -> MeshResult<String> {
+        Ok(try!(File::open(&Path::new("templates/model.pov")).read_to_string()))
     }
 }
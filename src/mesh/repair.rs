@@ -0,0 +1,401 @@
+// Fixing the problems validate() reports, as one pipeline of optional steps.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use mesh::{Facet,Mesh};
+use topology::Topology;
+use validate::is_degenerate;
+use vector::Vector3D;
+use hole::HoleOptions;
+use error::MeshResult;
+
+// Which steps to run. The defaults run all of them.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct RepairOptions {
+    // Weld tolerance; Some(0.0) merges exact duplicates only.
+    pub weld: Option<f32>,
+    pub remove_degenerate: bool,
+    pub remove_duplicates: bool,
+    // Shells with fewer facets than this are dropped, though never the
+    // largest one. Four is the fewest a closed shell can have.
+    pub min_shell_facets: usize,
+    pub unify_winding: bool,
+    pub orient_outward: bool,
+    // Holes of up to this many edges are filled.
+    pub fill_holes: Option<usize>,
+}
+
+impl RepairOptions {
+    pub fn new() -> RepairOptions {
+        RepairOptions {
+            weld: Some(0.0),
+            remove_degenerate: true,
+            remove_duplicates: true,
+            min_shell_facets: 4,
+            unify_winding: true,
+            orient_outward: true,
+            fill_holes: Some(16),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct RepairSummary {
+    pub merged_vertices: usize,
+    pub degenerate_facets: usize,
+    pub duplicate_facets: usize,
+    pub shells_removed: usize,
+    // Facets turned to agree with their neighbours.
+    pub facets_flipped: usize,
+    // Shells turned inside out so their normals point outward.
+    pub shells_flipped: usize,
+    pub holes_filled: usize,
+    pub facets_added: usize,
+    // Vertices hole filling put inside its patches.
+    pub vertices_added: usize,
+    pub unused_vertices: usize,
+}
+
+impl fmt::Display for RepairSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "merged {} vertices, removed {} degenerate and {} duplicate facets and {} shells, \
+                   flipped {} facets and {} shells, filled {} holes with {} facets and {} vertices, \
+                   dropped {} unused vertices",
+               self.merged_vertices, self.degenerate_facets, self.duplicate_facets, self.shells_removed,
+               self.facets_flipped, self.shells_flipped, self.holes_filled, self.facets_added,
+               self.vertices_added, self.unused_vertices)
+    }
+}
+
+// Runs the chosen steps in an order where each helps the next: welding
+// first so the others see the real connectivity, clean-up before winding,
+// and holes filled before deciding which way a shell faces, since that is
+// only well defined for closed shells.
+pub fn repair(mesh: &mut Mesh, opts: &RepairOptions) -> MeshResult<RepairSummary> {
+    try!(mesh.check_indices());
+    let mut summary = RepairSummary {
+        merged_vertices: 0,
+        degenerate_facets: 0,
+        duplicate_facets: 0,
+        shells_removed: 0,
+        facets_flipped: 0,
+        shells_flipped: 0,
+        holes_filled: 0,
+        facets_added: 0,
+        vertices_added: 0,
+        unused_vertices: 0,
+    };
+    if let Some(epsilon) = opts.weld {
+        let report = try!(mesh.weld(epsilon));
+        summary.merged_vertices = report.merged_vertices;
+        summary.degenerate_facets += report.dropped_facets;
+    }
+    if opts.remove_degenerate {
+        let vertices = mesh.vertices.clone();
+        summary.degenerate_facets += mesh.retain_facets(|_, f| {
+            !is_degenerate(vertices[f.v1], vertices[f.v2], vertices[f.v3])
+        });
+    }
+    if opts.remove_duplicates {
+        let mut seen = HashSet::new();
+        summary.duplicate_facets = mesh.retain_facets(|_, f| {
+            let mut key = [f.v1, f.v2, f.v3];
+            key.sort();
+            seen.insert(key)
+        });
+    }
+    if opts.min_shell_facets > 0 {
+        summary.shells_removed = try!(remove_small_shells(mesh, opts.min_shell_facets));
+    }
+    if opts.unify_winding {
+        summary.facets_flipped = try!(unify_winding(mesh));
+    }
+    if let Some(max_edges) = opts.fill_holes {
+        let report = try!(mesh.fill_holes(&HoleOptions { max_edges: max_edges, ..HoleOptions::new() }));
+        summary.holes_filled = report.holes_filled;
+        summary.facets_added = report.facets_added;
+        summary.vertices_added = report.vertices_added;
+    }
+    if opts.orient_outward {
+        summary.shells_flipped = try!(orient_outward(mesh));
+    }
+    summary.unused_vertices = mesh.remove_unused_vertices();
+    Ok(summary)
+}
+
+// Builds the connectivity of the mesh, which is lent to the topology for
+// the duration.
+fn with_topology<T, F: FnOnce(&Topology) -> T>(mesh: &mut Mesh, f: F) -> MeshResult<T> {
+    let topo = try!(Topology::new(mem::replace(mesh, Mesh::new())));
+    let result = f(&topo);
+    *mesh = topo.into_mesh();
+    Ok(result)
+}
+
+fn remove_small_shells(mesh: &mut Mesh, min_facets: usize) -> MeshResult<usize> {
+    let shells = try!(with_topology(mesh, |topo| topo.shells()));
+    let count = shells.iter().map(|s| s + 1).max().unwrap_or(0);
+    let mut sizes = vec![0usize; count];
+    for &s in shells.iter() {
+        sizes[s] += 1;
+    }
+    let largest = (0..count).max_by(|&s| sizes[s]).unwrap_or(0);
+    let small: Vec<bool> = (0..count).map(|s| s != largest && sizes[s] < min_facets).collect();
+    mesh.retain_facets(|i, _| !small[shells[i]]);
+    Ok(small.iter().filter(|s| **s).count())
+}
+
+// Walks each shell from its first facet across edges with exactly two
+// facets, flipping any neighbour that runs the shared edge the same way as
+// the facet it was reached from. Returns how many facets were flipped.
+fn unify_winding(mesh: &mut Mesh) -> MeshResult<usize> {
+    let flipped = try!(with_topology(mesh, |topo| {
+        let count = topo.mesh().facets.len();
+        let mut seen = vec![false; count];
+        let mut flipped = vec![false; count];
+        let hes = topo.half_edges();
+        // The origin of a half-edge once its facet's flip is applied.
+        let origin = |h: usize, flipped: &Vec<bool>| {
+            if flipped[hes[h].facet] { hes[h].target } else { hes[h].origin }
+        };
+        for start in 0..count {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut stack = vec![start];
+            while let Some(f) = stack.pop() {
+                for h in 3 * f..3 * f + 3 {
+                    let edge = &topo.edges()[hes[h].edge];
+                    if edge.half_edges.len() != 2 {
+                        continue;
+                    }
+                    let g = if edge.half_edges[0] == h { edge.half_edges[1] } else { edge.half_edges[0] };
+                    let n = hes[g].facet;
+                    if seen[n] {
+                        continue;
+                    }
+                    seen[n] = true;
+                    if origin(g, &flipped) == origin(h, &flipped) {
+                        flipped[n] = true;
+                    }
+                    stack.push(n);
+                }
+            }
+        }
+        flipped
+    }));
+    let mut count = 0;
+    for (f, flip) in mesh.facets.iter_mut().zip(flipped.iter()) {
+        if *flip {
+            flip_facet(f);
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// Turns closed shells inside out where they face the wrong way for how
+// deeply they are nested: a shell inside an odd number of others bounds a
+// cavity and faces inward, any other shell faces outward, which is when its
+// enclosed volume is positive. Shells that are still open enclose nothing
+// and are left alone. Returns how many were turned.
+fn orient_outward(mesh: &mut Mesh) -> MeshResult<usize> {
+    let (shells, open) = try!(with_topology(mesh, |topo| {
+        let shells = topo.shells();
+        let open: HashSet<usize> = topo.edges().iter()
+            .filter(|e| e.half_edges.len() == 1)
+            .map(|e| shells[topo.half_edges()[e.half_edges[0]].facet])
+            .collect();
+        (shells, open)
+    }));
+    let count = shells.iter().map(|s| s + 1).max().unwrap_or(0);
+    let mut volumes = vec![0.0f64; count];
+    let mut corners = vec![None; count];
+    for (f, &s) in mesh.facets.iter().zip(shells.iter()) {
+        let (a, b, c) = (mesh.vertices[f.v1], mesh.vertices[f.v2], mesh.vertices[f.v3]);
+        volumes[s] += Vector3D::dot(a, Vector3D::cross(b, c)) as f64;
+        if corners[s].is_none() {
+            corners[s] = Some(a);
+        }
+    }
+    let mut flip = vec![false; count];
+    for s in (0..count).filter(|s| !open.contains(s)) {
+        // Shells do not cross, so any point of this one is inside another
+        // when a ray from it crosses that one an odd number of times.
+        let origin = corners[s].unwrap();
+        let mut crossings = vec![0usize; count];
+        for (f, &t) in mesh.facets.iter().zip(shells.iter()) {
+            if t != s && !open.contains(&t) &&
+               ray_crosses(origin, mesh.vertices[f.v1], mesh.vertices[f.v2], mesh.vertices[f.v3]) {
+                crossings[t] += 1;
+            }
+        }
+        let depth = crossings.iter().filter(|c| **c % 2 == 1).count();
+        flip[s] = (volumes[s] < 0.0) != (depth % 2 == 1);
+    }
+    for (f, &s) in mesh.facets.iter_mut().zip(shells.iter()) {
+        if flip[s] {
+            flip_facet(f);
+        }
+    }
+    Ok(flip.iter().filter(|f| **f).count())
+}
+
+// A direction no axis-aligned edge or diagonal runs along, so rays rarely
+// graze an edge of the common boxy shapes.
+const RAY: Vector3D = Vector3D { x: 0.5, y: 0.6, z: 0.7 };
+
+// Whether the ray from `origin` along RAY passes through the triangle
+// (Möller and Trumbore's test).
+fn ray_crosses(origin: Vector3D, a: Vector3D, b: Vector3D, c: Vector3D) -> bool {
+    let (e1, e2) = (b.minus(a), c.minus(a));
+    let p = Vector3D::cross(RAY, e2);
+    let det = Vector3D::dot(e1, p);
+    if det == 0.0 {
+        return false;
+    }
+    let d = origin.minus(a);
+    let u = Vector3D::dot(d, p) / det;
+    if u < 0.0 || u > 1.0 {
+        return false;
+    }
+    let q = Vector3D::cross(d, e1);
+    let v = Vector3D::dot(RAY, q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return false;
+    }
+    Vector3D::dot(e2, q) / det > 0.0
+}
+
+fn flip_facet(f: &mut Facet) {
+    mem::swap(&mut f.v2, &mut f.v3);
+    f.n = f.n.scale(-1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Facet,Mesh};
+    use vector::Vector3D;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3D {
+        Vector3D { x: x, y: y, z: z }
+    }
+
+    fn f(a: usize, b: usize, c: usize) -> Facet {
+        Facet { v1: a, v2: b, v3: c, n: v(0.0, 0.0, 0.0) }
+    }
+
+    fn tetrahedron() -> Mesh {
+        Mesh::new_from_parts(vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0), v(0.0, 0.0, 1.0)],
+                             vec![f(0, 2, 1), f(0, 1, 3), f(1, 2, 3), f(0, 3, 2)])
+    }
+
+    #[test]
+    fn broken_tetrahedron_is_repaired() {
+        let mut mesh = tetrahedron();
+        // Every facet inside out but one, which is also missing: the hole
+        // has to be filled before the shell can be turned the right way.
+        mesh.facets = vec![f(0, 1, 2), f(0, 3, 1), f(1, 3, 2)];
+        // A copy of the first vertex, a duplicate and a degenerate facet,
+        // and a stray triangle far away.
+        mesh.vertices.push(v(0.0, 0.0, 0.0));
+        mesh.facets.push(f(4, 3, 1));
+        mesh.facets.push(f(2, 1, 0));
+        mesh.facets.push(f(1, 2, 2));
+        mesh.vertices.push(v(9.0, 9.0, 9.0));
+        mesh.vertices.push(v(9.0, 8.0, 9.0));
+        mesh.vertices.push(v(8.0, 9.0, 9.0));
+        mesh.facets.push(f(5, 6, 7));
+        let summary = repair(&mut mesh, &RepairOptions::new()).unwrap();
+        assert_eq!(summary, RepairSummary {
+            merged_vertices: 1,
+            degenerate_facets: 1,
+            duplicate_facets: 2,
+            shells_removed: 1,
+            facets_flipped: 0,
+            shells_flipped: 1,
+            holes_filled: 1,
+            facets_added: 1,
+            vertices_added: 0,
+            unused_vertices: 3,
+        });
+        assert!(summary.to_string().contains("filled 1 holes with 1 facets and 0 vertices"));
+        assert!(mesh.validate().is_valid(), "{:?}", mesh.validate());
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.facets.len(), 4);
+    }
+
+    // The cube from `lo` to `hi`, facing outward or, for a cavity, inward.
+    fn cube(mesh: &mut Mesh, lo: f32, hi: f32, inward: bool) {
+        let base = mesh.vertices.len();
+        for i in 0..8 {
+            let c = |bit: usize| if i & bit != 0 { hi } else { lo };
+            mesh.vertices.push(v(c(1), c(2), c(4)));
+        }
+        let sides = [[0, 2, 3], [0, 3, 1], [4, 5, 7], [4, 7, 6], [0, 1, 5], [0, 5, 4],
+                     [2, 6, 7], [2, 7, 3], [0, 4, 6], [0, 6, 2], [1, 3, 7], [1, 7, 5]];
+        for t in sides.iter() {
+            let (a, b, c) = (base + t[0], base + t[1], base + t[2]);
+            mesh.facets.push(if inward { f(a, c, b) } else { f(a, b, c) });
+        }
+    }
+
+    #[test]
+    fn cavities_keep_facing_inward() {
+        let mut mesh = Mesh::new();
+        cube(&mut mesh, 0.0, 3.0, false);
+        cube(&mut mesh, 1.0, 2.0, true);
+        let original = Mesh::new_from_parts(mesh.vertices.clone(), mesh.facets.clone());
+        let summary = repair(&mut mesh, &RepairOptions::new()).unwrap();
+        assert_eq!(summary.shells_flipped, 0);
+        assert_eq!(mesh, original);
+
+        // Both turned the wrong way round, they are both put right.
+        let mut mesh = Mesh::new();
+        cube(&mut mesh, 0.0, 3.0, true);
+        cube(&mut mesh, 1.0, 2.0, false);
+        assert_eq!(repair(&mut mesh, &RepairOptions::new()).unwrap().shells_flipped, 2);
+        assert_eq!(mesh.vertices, original.vertices);
+        let mut facets: Vec<[usize; 3]> = mesh.facets.iter().map(|f| sorted_from_min(f)).collect();
+        let mut expected: Vec<[usize; 3]> = original.facets.iter().map(|f| sorted_from_min(f)).collect();
+        facets.sort();
+        expected.sort();
+        assert_eq!(facets, expected);
+    }
+
+    // A facet's corners rotated to start at the smallest, which keeps the
+    // winding.
+    fn sorted_from_min(f: &Facet) -> [usize; 3] {
+        let c = [f.v1, f.v2, f.v3];
+        let m = (0..3).min_by(|&i| c[i]).unwrap();
+        [c[m], c[(m + 1) % 3], c[(m + 2) % 3]]
+    }
+
+    #[test]
+    fn open_shells_are_not_turned() {
+        // Inside out and missing two facets, so the hole is too long to
+        // fill with a limit of three edges.
+        let mut mesh = Mesh::new();
+        cube(&mut mesh, 0.0, 1.0, true);
+        mesh.facets.truncate(10);
+        let opts = RepairOptions { fill_holes: Some(3), ..RepairOptions::new() };
+        let summary = repair(&mut mesh, &opts).unwrap();
+        assert_eq!((summary.holes_filled, summary.shells_flipped), (0, 0));
+        assert_eq!(mesh.facets[0], f(0, 3, 2));
+    }
+
+    #[test]
+    fn winding_follows_the_first_facet() {
+        let mut mesh = tetrahedron();
+        mesh.facets[2] = f(1, 3, 2);
+        let opts = RepairOptions { orient_outward: false, ..RepairOptions::new() };
+        let summary = repair(&mut mesh, &opts).unwrap();
+        assert_eq!(summary.facets_flipped, 1);
+        assert!(mesh.validate().is_valid());
+    }
+}
@@ -4,13 +4,24 @@
 #![deny(unused_imports)]
 
 // This guy depends on multiple sibling sub-modules, so he can use that here.
-use std::old_io::{IoResult,Reader,Writer};
+use std::old_io::{IoErrorKind,Reader,Seek,SeekStyle,Writer};
+use std::old_path::Path;
+use std::cmp;
 use std::fmt;
 use std::mem;
+use std::num::Float;
+use std::str;
+use std::thread;
 use mesh::Mesh;
-use mesh::Facet;
+use mesh::{Color,Facet};
 use vector::Vector3D;
 use vector::VertexMap;
+use parse::{Tokens,parse_error};
+use error::{MeshError,MeshResult};
+use mmap::MappedFile;
+
+// Below this many facets per thread, spawning costs more than it saves.
+const MIN_FACETS_PER_THREAD: usize = 16384;
 
 pub struct StlHeader {
     header: [u8; 80],
@@ -33,7 +44,7 @@ impl fmt::Debug for StlFacet {
 }
 
 impl StlFacet {
-    pub fn read(r: &mut Reader) -> IoResult<StlFacet> {
+    pub fn read(r: &mut Reader) -> MeshResult<StlFacet> {
         let n = try!(Vector3D::read(r));
         let v1 = try!(Vector3D::read(r));
         let v2 = try!(Vector3D::read(r));
@@ -42,7 +53,13 @@ impl StlFacet {
         Ok(StlFacet { n:n, v1:v1, v2:v2, v3:v3, abc:abc })
     }
 
-    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+    // Decodes one 50 byte record straight from a buffer.
+    pub fn decode(b: &[u8]) -> StlFacet {
+        let v = |o: usize| Vector3D { x: le_f32(&b[o..]), y: le_f32(&b[o + 4..]), z: le_f32(&b[o + 8..]) };
+        StlFacet { n: v(0), v1: v(12), v2: v(24), v3: v(36), abc: (b[48] as u16) | (b[49] as u16) << 8 }
+    }
+
+    pub fn write(&self, w: &mut Writer) -> MeshResult<()> {
         try!(self.n.write(w));
         try!(self.v1.write(w));
         try!(self.v2.write(w));
@@ -61,57 +78,228 @@ impl StlFacet {
     }
 }
 
+#[derive(PartialEq, Eq, Debug, Copy)]
+pub enum StlFormat {
+    Binary,
+    Ascii,
+}
+
+// How floats are spelled in ASCII output: the shortest form that reads back
+// to the same f32, or scientific notation with a fixed number of digits.
+#[derive(PartialEq, Eq, Debug, Copy)]
+pub enum FloatFormat {
+    Shortest,
+    Fixed(usize),
+}
+
+impl FloatFormat {
+    pub fn format(&self, v: f32) -> String {
+        match *self {
+            FloatFormat::Shortest => format!("{:e}", v),
+            FloatFormat::Fixed(digits) => format!("{:.*e}", digits, v),
+        }
+    }
+
+    fn vector(&self, v: Vector3D) -> String {
+        format!("{} {} {}", self.format(v.x), self.format(v.y), self.format(v.z))
+    }
+}
+
+// The two conventions for packing a 15 bit facet colour into the attribute
+// word. Materialise Magics writes "COLOR=" and a default RGBA colour into the
+// header, keeps red in the low bits and clears bit 15 on facets that have a
+// colour of their own. VisCAM and SolidView keep blue in the low bits and set
+// bit 15 on every facet whose colour is valid.
+#[derive(PartialEq, Eq, Debug, Copy)]
+pub enum StlColors {
+    Materialise(Color),
+    VisCam,
+}
+
+impl StlColors {
+    // Which convention, if any, a file uses: Materialise when the header
+    // says so, VisCAM when any facet has its valid bit set.
+    pub fn detect(header: &[u8], attributes: &[u16]) -> Option<StlColors> {
+        if let Some(i) = find(header, b"COLOR=") {
+            if i + 10 <= header.len() {
+                let c = &header[i + 6..i + 10];
+                return Some(StlColors::Materialise(Color::from_bytes(c[0], c[1], c[2], c[3])));
+            }
+        }
+        // Without COLOR= the diffuse part of the material is the default.
+        if let Some(i) = find(header, b"MATERIAL=") {
+            if i + 13 <= header.len() {
+                let c = &header[i + 9..i + 13];
+                return Some(StlColors::Materialise(Color::from_bytes(c[0], c[1], c[2], c[3])));
+            }
+        }
+        if attributes.iter().any(|a| a & 0x8000 != 0) {
+            Some(StlColors::VisCam)
+        } else {
+            None
+        }
+    }
+
+    // Facets without a colour of their own come out white (VisCAM) or in
+    // the default colour (Materialise).
+    pub fn decode(&self, abc: u16) -> Color {
+        let low = (abc & 31) as f32 / 31.0;
+        let mid = ((abc >> 5) & 31) as f32 / 31.0;
+        let high = ((abc >> 10) & 31) as f32 / 31.0;
+        match *self {
+            StlColors::Materialise(default) if abc & 0x8000 != 0 => default,
+            StlColors::Materialise(_) => Color::rgb(low, mid, high),
+            StlColors::VisCam if abc & 0x8000 == 0 => Color::white(),
+            StlColors::VisCam => Color::rgb(high, mid, low),
+        }
+    }
+
+    // Alpha is dropped; there are no bits left for it.
+    pub fn encode(&self, c: Color) -> u16 {
+        fn five(v: f32) -> u16 {
+            (v.max(0.0).min(1.0) * 31.0 + 0.5) as u16
+        }
+        match *self {
+            StlColors::Materialise(default) if c == default => 0x8000,
+            StlColors::Materialise(_) => five(c.r) | five(c.g) << 5 | five(c.b) << 10,
+            StlColors::VisCam => 0x8000 | five(c.r) << 10 | five(c.g) << 5 | five(c.b),
+        }
+    }
+}
+
 pub struct StlFile {
     pub header: [u8; 80],
     pub facets: Vec<StlFacet>,
+    pub format: StlFormat,
 }
 
 impl StlFile {
 
-    pub fn read(r: &mut Reader) -> IoResult<StlFile> {
+    pub fn read(r: &mut Reader) -> MeshResult<StlFile> {
+        let data = try!(r.read_to_end());
+        StlFile::read_bytes(&data)
+    }
+
+    pub fn read_bytes(data: &[u8]) -> MeshResult<StlFile> {
         let mut file = StlFile {
             header: [0u8; 80],
             facets: Vec::new(),
+            format: StlFormat::Binary,
         };
-        try!(r.read_at_least(file.header.len(), &mut file.header));
-        // TODO: select which form to read: binary or ascii
-        try!(file.read_binary(r));
+        if is_binary(data) {
+            try!(file.read_binary(data, 1));
+        } else {
+            file.format = StlFormat::Ascii;
+            try!(file.read_ascii(data));
+        }
         Ok(file)
     }
 
-    /*
-    fn read_ascii(r: &mut Reader) -> IoResult<Mesh> {
-        //solid vcg
-        //  facet normal 7.733874e-001 -3.151335e-002 6.331499e-001
-        //    outer loop
-        //      vertex  2.137833e+001 5.385936e+001 1.350253e+001
-        //      vertex  2.341920e+001 4.700068e+001 1.066826e+001
-        //      vertex  2.859041e+001 5.985682e+001 4.991545e+000
-        //    endloop
-        //  endfacet
-        //endsolid vcg
-        for line in r.lines() {
-            print!("{}", line.unwrap());
+    // solid vcg
+    //   facet normal 7.733874e-001 -3.151335e-002 6.331499e-001
+    //     outer loop
+    //       vertex  2.137833e+001 5.385936e+001 1.350253e+001
+    //       vertex  2.341920e+001 4.700068e+001 1.066826e+001
+    //       vertex  2.859041e+001 5.985682e+001 4.991545e+000
+    //     endloop
+    //   endfacet
+    // endsolid vcg
+    //
+    // A file may hold several solids one after another; their facets are
+    // all collected and the first solid's name is kept in the header.
+    fn read_ascii(&mut self, data: &[u8]) -> MeshResult<&StlFile> {
+        let text = match str::from_utf8(data) {
+            Ok(t) => t,
+            Err(_) => return Err(parse_error(1, "ASCII STL is not valid text".to_string())),
+        };
+        let mut tokens = Tokens::new(text);
+        let mut solids = 0;
+        while tokens.peek().is_some() {
+            try!(tokens.expect("solid"));
+            let mut name = String::new();
+            while let Some(word) = tokens.next_on_line() {
+                if !name.is_empty() {
+                    name.push(' ');
+                }
+                name.push_str(word);
+            }
+            if solids == 0 {
+                self.set_header_text(&format!("solid {}", name));
+            }
+            solids += 1;
+            loop {
+                match tokens.next() {
+                    Some("facet") => {
+                        let f = try!(read_ascii_facet(&mut tokens));
+                        self.facets.push(f);
+                    },
+                    Some("endsolid") => {
+                        while tokens.next_on_line().is_some() {}
+                        break;
+                    },
+                    Some(t) => return Err(parse_error(tokens.line(),
+                        format!("expected 'facet' or 'endsolid', found '{}'", t))),
+                    None => return Err(parse_error(tokens.line(),
+                        "missing 'endsolid'".to_string())),
+                }
+            }
+        }
+        if solids == 0 {
+            return Err(parse_error(tokens.line(), "no solid found".to_string()));
         }
-        Mesh::new() // TODO
+        Ok(self)
     }
-    */
 
-    fn read_binary(&mut self, r: &mut Reader) -> IoResult<&StlFile> {
-        let facet_count = try!(r.read_le_u32());
-        for _ in 0..facet_count {
-            let f = try!(StlFacet::read(r));
-            self.facets.push(f);
+    // Maps the file into memory and decodes binary facets from the mapping
+    // straight into the indexed mesh, spreading them over up to `threads`
+    // threads. Meant for huge binary files, but ASCII ones are read too.
+    pub fn read_mapped(path: &Path, threads: usize) -> MeshResult<Mesh> {
+        let map = try!(MappedFile::open(path));
+        let data = map.as_slice();
+        if !is_binary(data) {
+            return StlFile::read_bytes(data).map(|f| f.as_mesh());
         }
+        let (header, records) = try!(binary_records(data));
+        let (mut mesh, attributes) = decode_mesh(records, threads);
+        set_binary_extras(&mut mesh, header, attributes);
+        Ok(mesh)
+    }
+
+    fn read_binary(&mut self, data: &[u8], threads: usize) -> MeshResult<&StlFile> {
+        let (header, records) = try!(binary_records(data));
+        for (i, b) in header.iter().enumerate() {
+            self.header[i] = *b;
+        }
+        self.facets = decode_facets(records, threads);
         Ok(self)
     }
 
-    pub fn write_binary(m: &Mesh, w: &mut Writer) -> IoResult<()> {
+    fn set_header_text(&mut self, text: &str) {
+        self.header = [0u8; 80];
+        for (i, b) in text.bytes().take(self.header.len()).enumerate() {
+            self.header[i] = b;
+        }
+    }
+
+    // Writes the mesh's own header and facet attributes when it has them,
+    // zeros otherwise. Facet colours are packed into the attributes in the
+    // convention the header asks for, VisCAM by default; a word that already
+    // decodes to the facet's colour is kept as it is.
+    pub fn write_binary(m: &Mesh, w: &mut Writer) -> MeshResult<()> {
+        try!(m.check_indices());
+        let colors = if m.facet_colors.is_empty() {
+            None
+        } else {
+            Some(StlColors::detect(&m.stl_header, &[]).unwrap_or(StlColors::VisCam))
+        };
 
         //IMPORTANT! STL files assume little endian
-        let header = StlHeader { header: [0u8; 80] };
+        let mut header = StlHeader { header: [0u8; 80] };
+        for (i, b) in m.stl_header.iter().take(header.header.len()).enumerate() {
+            header.header[i] = *b;
+        }
 
-        // write the blank header
+        // write the header
         for i in 0..mem::size_of_val(&header) {
             try!(w.write_u8(header.header[i]));
         }
@@ -129,7 +317,7 @@ impl StlFile {
                 n:facet.n, v1:m.vertices[facet.v1],
                 v2:m.vertices[facet.v2],
                 v3:m.vertices[facet.v3],
-                abc:0 as u16
+                abc:attribute(m, i, colors)
             };
 
             try!(stl_facet.write(w));
@@ -138,23 +326,42 @@ impl StlFile {
        Ok(())
     }
 
+    pub fn write_ascii(m: &Mesh, w: &mut Writer, name: &str, ff: FloatFormat) -> MeshResult<()> {
+        try!(m.check_indices());
+        try!(writeln!(w, "solid {}", name));
+        for facet in m.facets.iter() {
+            try!(writeln!(w, "  facet normal {}", ff.vector(facet.n)));
+            try!(writeln!(w, "    outer loop"));
+            try!(writeln!(w, "      vertex {}", ff.vector(m.vertices[facet.v1])));
+            try!(writeln!(w, "      vertex {}", ff.vector(m.vertices[facet.v2])));
+            try!(writeln!(w, "      vertex {}", ff.vector(m.vertices[facet.v3])));
+            try!(writeln!(w, "    endloop"));
+            try!(writeln!(w, "  endfacet"));
+        }
+        try!(writeln!(w, "endsolid {}", name));
+        Ok(())
+    }
+
     pub fn kind(&self) -> String {
-        let hs = String::from_utf8_lossy(&self.header);
-        if hs.starts_with("solid ") {
-            "ASCII".to_string()
-        } else {
-            "binary".to_string()
+        match self.format {
+            StlFormat::Ascii => "ASCII".to_string(),
+            StlFormat::Binary => "binary".to_string(),
         }
     }
 
     pub fn as_mesh(&self) -> Mesh {
         let mut vmap = VertexMap::new();
-        for f in self.facets.iter() {
-            vmap.add(f.v1);
-            vmap.add(f.v2);
-            vmap.add(f.v3);
+        let facets = indexed_vertices(&self.facets, &mut vmap);
+        let mut mesh = Mesh::new_from_parts(vmap.into_vector(), facets);
+        if self.format == StlFormat::Binary {
+            set_binary_extras(&mut mesh, &self.header, self.facets.iter().map(|f| f.abc).collect());
         }
-        Mesh::new_from_parts(vmap.vector(), indexed_vertices(&self.facets, &vmap))
+        mesh
+    }
+
+    // The header up to its first NUL, as text.
+    pub fn header_text(&self) -> String {
+        header_text(&self.header)
     }
 
     pub fn println_debug(&self) {
@@ -166,12 +373,290 @@ impl StlFile {
     }
 }
 
-fn indexed_vertices(fv: &Vec<StlFacet>, vm: &VertexMap) -> Vec<Facet> {
+// Reads the facets of a binary STL one at a time, so files of any size can
+// be processed without holding them in memory. Wrap unbuffered readers in a
+// BufferedReader first. ASCII files have to go through StlFile::read; new
+// turns them away when the header says "solid" and the triangle count is
+// made of text characters, which would mean over 150 million triangles.
+pub struct StlFacetReader<R> {
+    r: R,
+    pub header: [u8; 80],
+    count: u32,
+    read: u32,
+}
+
+impl<R: Reader> StlFacetReader<R> {
+    pub fn new(mut r: R) -> MeshResult<StlFacetReader<R>> {
+        let mut header = [0u8; 80];
+        try!(r.read_at_least(header.len(), &mut header));
+        let mut raw = [0u8; 4];
+        try!(r.read_at_least(raw.len(), &mut raw));
+        let text = |b: &u8| *b == b'\t' || *b == b'\n' || *b == b'\r' || (*b >= 0x20 && *b < 0x7f);
+        let start = header.iter().position(|b| !(*b as char).is_whitespace()).unwrap_or(header.len());
+        if header[start..].starts_with(b"solid") && raw.iter().all(text) {
+            return Err(parse_error(0, "input looks like an ASCII STL, which cannot be streamed".to_string()));
+        }
+        let count = (raw[0] as u32) | (raw[1] as u32) << 8 | (raw[2] as u32) << 16 | (raw[3] as u32) << 24;
+        Ok(StlFacetReader { r: r, header: header, count: count, read: 0 })
+    }
+
+    // The triangle count the header declares.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+impl<R: Reader> Iterator for StlFacetReader<R> {
+    type Item = MeshResult<StlFacet>;
+
+    // A file that ends early yields one error and then stops.
+    fn next(&mut self) -> Option<MeshResult<StlFacet>> {
+        if self.read == self.count {
+            return None;
+        }
+        match StlFacet::read(&mut self.r) {
+            Ok(f) => {
+                self.read += 1;
+                Some(Ok(f))
+            },
+            Err(MeshError::Io(ref e)) if e.kind == IoErrorKind::EndOfFile => {
+                let msg = format!("file ends after {} of {} triangles", self.read, self.count);
+                self.read = self.count;
+                Some(Err(parse_error(0, msg)))
+            },
+            Err(e) => {
+                self.read = self.count;
+                Some(Err(e))
+            },
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = (self.count - self.read) as usize;
+        (0, Some(left))
+    }
+}
+
+// Writes a binary STL one facet at a time. The triangle count is not known
+// until the end, so a placeholder is written and finish() seeks back to
+// fill it in.
+pub struct StlFacetWriter<W> {
+    w: W,
+    count: u64,
+}
+
+impl<W: Writer + Seek> StlFacetWriter<W> {
+    pub fn new(mut w: W, header: &[u8]) -> MeshResult<StlFacetWriter<W>> {
+        let mut padded = [0u8; 80];
+        for (i, b) in header.iter().take(padded.len()).enumerate() {
+            padded[i] = *b;
+        }
+        try!(w.write_all(&padded));
+        try!(w.write_le_u32(0));
+        Ok(StlFacetWriter { w: w, count: 0 })
+    }
+
+    pub fn write(&mut self, f: &StlFacet) -> MeshResult<()> {
+        try!(f.write(&mut self.w));
+        self.count += 1;
+        Ok(())
+    }
+
+    // Patches the triangle count and hands the sink back, positioned at the
+    // end of the file.
+    pub fn finish(mut self) -> MeshResult<W> {
+        if self.count > ::std::u32::MAX as u64 {
+            return Err(MeshError::InvalidArgument(
+                format!("binary STL holds at most {} triangles, not {}", ::std::u32::MAX, self.count)));
+        }
+        try!(self.w.seek(80, SeekStyle::SeekSet));
+        try!(self.w.write_le_u32(self.count as u32));
+        try!(self.w.seek(0, SeekStyle::SeekEnd));
+        try!(self.w.flush());
+        Ok(self.w)
+    }
+}
+
+fn read_ascii_facet(tokens: &mut Tokens) -> MeshResult<StlFacet> {
+    try!(tokens.expect("normal"));
+    let n = try!(read_ascii_vector(tokens));
+    try!(tokens.expect("outer"));
+    try!(tokens.expect("loop"));
+    let mut vs = [Vector3D { x: 0.0, y: 0.0, z: 0.0 }; 3];
+    for i in 0..3 {
+        try!(tokens.expect("vertex"));
+        vs[i] = try!(read_ascii_vector(tokens));
+    }
+    try!(tokens.expect("endloop"));
+    try!(tokens.expect("endfacet"));
+    Ok(StlFacet { n: n, v1: vs[0], v2: vs[1], v3: vs[2], abc: 0 })
+}
+
+fn read_ascii_vector(tokens: &mut Tokens) -> MeshResult<Vector3D> {
+    let x = try!(tokens.next_f32());
+    let y = try!(tokens.next_f32());
+    let z = try!(tokens.next_f32());
+    Ok(Vector3D { x: x, y: y, z: z })
+}
+
+fn attribute(m: &Mesh, i: usize, colors: Option<StlColors>) -> u16 {
+    let abc = *m.facet_attributes.get(i).unwrap_or(&0);
+    match colors {
+        Some(colors) if colors.decode(abc) != m.facet_colors[i] => colors.encode(m.facet_colors[i]),
+        _ => abc,
+    }
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+// Header bytes up to the first NUL, with anything that is not UTF-8 replaced.
+pub fn header_text(header: &[u8]) -> String {
+    let end = header.iter().position(|b| *b == 0).unwrap_or(header.len());
+    String::from_utf8_lossy(&header[..end]).into_owned()
+}
+
+// Whether a buffer plausibly holds an STL file of either kind.
+pub fn sniff(data: &[u8]) -> bool {
+    let start = data.iter().position(|b| !(*b as char).is_whitespace()).unwrap_or(data.len());
+    data[start..].starts_with(b"solid") || binary_size_matches(data)
+}
+
+fn binary_size_matches(data: &[u8]) -> bool {
+    if data.len() < 84 {
+        return false;
+    }
+    let count = (data[80] as u64) | (data[81] as u64) << 8 |
+                (data[82] as u64) << 16 | (data[83] as u64) << 24;
+    84 + 50 * count == data.len() as u64
+}
+
+// Decides whether a buffer holds a binary STL. Plenty of exporters start the
+// binary header with "solid", so a header match alone is not enough: a
+// triangle count that exactly accounts for the file size wins, and otherwise
+// a NUL near the start marks binary. Bytes above 0x7F prove nothing, as
+// solid names and comments may be UTF-8.
+fn is_binary(data: &[u8]) -> bool {
+    if binary_size_matches(data) {
+        return true;
+    }
+    let start = match data.iter().position(|b| !(*b as char).is_whitespace()) {
+        Some(i) => i,
+        None => return false,
+    };
+    if !data[start..].starts_with(b"solid") {
+        return true;
+    }
+    let sample = &data[..::std::cmp::min(data.len(), 1024)];
+    sample.iter().any(|b| *b == 0)
+}
+
+fn le_f32(b: &[u8]) -> f32 {
+    let bits = (b[0] as u32) | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+    unsafe { mem::transmute(bits) }
+}
+
+// The header and the facet records of a binary file. The declared triangle
+// count has to fit in the data; bytes past the last facet are ignored, as
+// some exporters pad their files.
+fn binary_records(data: &[u8]) -> MeshResult<(&[u8], &[u8])> {
+    if data.len() < 84 {
+        return Err(parse_error(0, format!("binary STL needs 84 bytes of header, found {}", data.len())));
+    }
+    let count = (data[80] as u64) | (data[81] as u64) << 8 |
+                (data[82] as u64) << 16 | (data[83] as u64) << 24;
+    let available = (data.len() as u64 - 84) / 50;
+    if count > available {
+        return Err(parse_error(0, format!("header declares {} triangles, but the file holds only {}",
+                                          count, available)));
+    }
+    Ok((&data[..80], &data[84..84 + 50 * count as usize]))
+}
+
+// Binary files also hand their header and, when any facet uses it, the
+// attribute words on to the mesh, along with the colours they encode.
+fn set_binary_extras(mesh: &mut Mesh, header: &[u8], attributes: Vec<u16>) {
+    mesh.stl_header = header.to_vec();
+    if let Some(colors) = StlColors::detect(header, &attributes) {
+        mesh.facet_colors = attributes.iter().map(|a| colors.decode(*a)).collect();
+    }
+    if attributes.iter().any(|a| *a != 0) {
+        mesh.facet_attributes = attributes;
+    }
+}
+
+// Decodes packed facet records, in order, splitting them into one run per
+// thread when there are enough of them.
+fn decode_facets(records: &[u8], threads: usize) -> Vec<StlFacet> {
+    let count = records.len() / 50;
+    let threads = cmp::max(1, cmp::min(threads, count / MIN_FACETS_PER_THREAD));
+    if threads == 1 {
+        return records.chunks(50).map(StlFacet::decode).collect();
+    }
+    let per_thread = (count + threads - 1) / threads;
+    let guards: Vec<_> = records.chunks(50 * per_thread).map(|run| {
+        thread::scoped(move || run.chunks(50).map(StlFacet::decode).collect::<Vec<StlFacet>>())
+    }).collect();
+    let mut facets = Vec::with_capacity(count);
+    for guard in guards.into_iter() {
+        facets.push_all(&guard.join());
+    }
+    facets
+}
+
+// Decodes packed facet records straight into an indexed mesh and the
+// attribute words, without collecting the facets first. With more than one
+// thread, each indexes its own run and the runs' vertices are then merged in
+// order, which gives the same indices as a single thread would.
+fn decode_mesh(records: &[u8], threads: usize) -> (Mesh, Vec<u16>) {
+    let count = records.len() / 50;
+    let threads = cmp::max(1, cmp::min(threads, count / MIN_FACETS_PER_THREAD));
+    if threads == 1 {
+        let (vertices, facets, attributes) = decode_run(records);
+        return (Mesh::new_from_parts(vertices, facets), attributes);
+    }
+    let per_thread = (count + threads - 1) / threads;
+    let guards: Vec<_> = records.chunks(50 * per_thread).map(|run| {
+        thread::scoped(move || decode_run(run))
+    }).collect();
+    let mut vmap = VertexMap::new();
+    let mut facets = Vec::with_capacity(count);
+    let mut attributes = Vec::with_capacity(count);
+    for guard in guards.into_iter() {
+        let (vertices, run_facets, run_attributes) = guard.join();
+        let remap: Vec<usize> = vertices.iter().map(|v| vmap.add(*v)).collect();
+        for f in run_facets.iter() {
+            facets.push(Facet { v1: remap[f.v1], v2: remap[f.v2], v3: remap[f.v3], n: f.n });
+        }
+        attributes.push_all(&run_attributes);
+    }
+    (Mesh::new_from_parts(vmap.into_vector(), facets), attributes)
+}
+
+// One run of records as its own vertices, the facets indexing them and the
+// facets' attribute words.
+fn decode_run(records: &[u8]) -> (Vec<Vector3D>, Vec<Facet>, Vec<u16>) {
+    let count = records.len() / 50;
+    let mut vmap = VertexMap::new();
+    let mut facets = Vec::with_capacity(count);
+    let mut attributes = Vec::with_capacity(count);
+    for record in records.chunks(50) {
+        let f = StlFacet::decode(record);
+        let (v1, v2, v3) = (vmap.add(f.v1), vmap.add(f.v2), vmap.add(f.v3));
+        facets.push(Facet { v1: v1, v2: v2, v3: v3, n: f.calculate_normal_vector() });
+        attributes.push(f.abc);
+    }
+    (vmap.into_vector(), facets, attributes)
+}
+
+// Adds each facet's corners to the map and indexes them from it.
+fn indexed_vertices(fv: &Vec<StlFacet>, vm: &mut VertexMap) -> Vec<Facet> {
     let mut v: Vec<Facet> = Vec::with_capacity(fv.len());
     for f in fv.iter() {
-        let v1 = vm.get(&f.v1);
-        let v2 = vm.get(&f.v2);
-        let v3 = vm.get(&f.v3);
+        let v1 = vm.add(f.v1);
+        let v2 = vm.add(f.v2);
+        let v3 = vm.add(f.v3);
         // I wish there was a better place to put this
         let n = f.calculate_normal_vector();
         v.push(Facet{
@@ -188,8 +673,15 @@ fn indexed_vertices(fv: &Vec<StlFacet>, vm: &VertexMap) -> Vec<Facet> {
 mod tests {
 
     use super::*;
-    use std::old_io::BufferedReader;
+    use super::{MIN_FACETS_PER_THREAD,decode_facets,decode_mesh};
+    use error::MeshError;
+    use parse::parse_error;
+    use off::OffFile;
+    use mesh::{Color,Facet,Mesh};
+    use vector::Vector3D;
+    use std::old_io::{BufReader,BufferedReader,TempDir};
     use std::old_io::BufferedWriter;
+    use std::old_io::MemWriter;
     use std::old_io::fs::File;
 
     #[test]
@@ -224,4 +716,258 @@ mod tests {
         let mesh3 = stl3.as_mesh();
         assert_eq!(mesh3, mesh2);
     }
+
+    #[test]
+    fn can_read_ascii() {
+        let text = "solid cube corner\n\
+                    facet normal 0 0 1\n\
+                      outer loop\n\
+                        vertex 0 0 0\n\
+                        vertex 1.0e+000 0 0\n\
+                        vertex 0 1E0 0\n\
+                      endloop\n\
+                    endfacet\n\
+                    endsolid cube corner\n\
+                    solid second\n\
+                    facet normal 0 0 -1\n\
+                      outer loop\n\
+                        vertex 0 0 0\n\
+                        vertex 0 1 0\n\
+                        vertex 1 0 0\n\
+                      endloop\n\
+                    endfacet\n\
+                    endsolid\n";
+        let stl = StlFile::read_bytes(text.as_bytes()).unwrap();
+        assert_eq!(stl.format, StlFormat::Ascii);
+        assert_eq!(stl.facets.len(), 2);
+        assert_eq!(stl.facets[0].v2.x, 1.0);
+        assert_eq!(stl.facets[0].v3.y, 1.0);
+        assert_eq!(stl.as_mesh().vertices.len(), 3);
+    }
+
+    #[test]
+    fn utf8_solid_names_stay_ascii() {
+        let text = "solid Würfel – Ecke\n\
+                    facet normal 0 0 1\n\
+                      outer loop\n\
+                        vertex 0 0 0\n\
+                        vertex 1 0 0\n\
+                        vertex 0 1 0\n\
+                      endloop\n\
+                    endfacet\n\
+                    endsolid Würfel – Ecke\n";
+        let stl = StlFile::read_bytes(text.as_bytes()).unwrap();
+        assert_eq!(stl.format, StlFormat::Ascii);
+        assert_eq!(stl.facets.len(), 1);
+        assert_eq!(stl.header_text(), "solid Würfel – Ecke".to_string());
+    }
+
+    #[test]
+    fn can_write_ascii() {
+        let stl = StlFile::read(&mut File::open(&Path::new("./Lily_Pad.stl"))).unwrap();
+        let mesh = stl.as_mesh();
+
+        let mut w = MemWriter::new();
+        StlFile::write_ascii(&mesh, &mut w, "lily pad", FloatFormat::Shortest).unwrap();
+        let text = w.into_inner();
+        assert!(text.starts_with(b"solid lily pad\n"));
+
+        let stl2 = StlFile::read_bytes(&text).unwrap();
+        assert_eq!(stl2.format, StlFormat::Ascii);
+        assert_eq!(stl2.as_mesh(), mesh);
+    }
+
+    #[test]
+    fn fixed_float_format() {
+        assert_eq!(FloatFormat::Fixed(3).format(21.37833), "2.138e1");
+        assert_eq!(FloatFormat::Shortest.format(0.5), "5e-1");
+    }
+
+    #[test]
+    fn ascii_errors_report_line() {
+        let text = "solid x\nfacet normal 0 0 1\nouter loop\nvertex 0 0 zero\n";
+        let err = StlFile::read_bytes(text.as_bytes()).err().unwrap();
+        assert_eq!(err, MeshError::Parse { line: 4, msg: "invalid number 'zero'".to_string() });
+    }
+
+    #[test]
+    fn writing_a_bad_index_fails() {
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mesh = Mesh::new_from_parts(vec![zero; 2], vec![Facet { v1: 0, v2: 1, v3: 2, n: zero }]);
+        let err = StlFile::write_binary(&mesh, &mut MemWriter::new()).err().unwrap();
+        assert_eq!(err, MeshError::InvalidIndex { facet: 0, index: 2, vertices: 2 });
+    }
+
+    #[test]
+    fn binary_keeps_header_and_attributes() {
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut mesh = Mesh::new_from_parts(vec![zero; 3], vec![Facet { v1: 0, v2: 1, v3: 2, n: zero }]);
+        mesh.stl_header = b"exported by meshman".to_vec();
+        mesh.facet_attributes = vec![0x8123];
+        let mut w = MemWriter::new();
+        StlFile::write_binary(&mesh, &mut w).unwrap();
+        let stl = StlFile::read_bytes(&w.into_inner()).unwrap();
+        assert_eq!(stl.header_text(), "exported by meshman");
+        assert_eq!(stl.facets[0].abc, 0x8123);
+        let read = stl.as_mesh();
+        assert_eq!(read.stl_header.len(), 80);
+        assert_eq!(read.facet_attributes, vec![0x8123]);
+    }
+
+    #[test]
+    fn decodes_both_color_conventions() {
+        let red = Color::rgb(1.0, 0.0, 0.0);
+        let viscam = StlColors::VisCam;
+        assert_eq!(StlColors::detect(&[0; 80], &[0, 0x8000 | 31 << 10]), Some(viscam));
+        assert_eq!(viscam.decode(0x8000 | 31 << 10), red);
+        assert_eq!(viscam.decode(31 << 10), Color::white());
+        assert_eq!(viscam.encode(red), 0x8000 | 31 << 10);
+
+        let mut header = b"COLOR=".to_vec();
+        header.push_all(&[0, 0, 255, 255]);
+        let blue = Color::rgb(0.0, 0.0, 1.0);
+        let magics = StlColors::detect(&header, &[]).unwrap();
+        assert_eq!(magics, StlColors::Materialise(blue));
+        assert_eq!(magics.decode(31), red);
+        assert_eq!(magics.decode(0x8000 | 31), blue);
+        assert_eq!(magics.encode(red), 31);
+        assert_eq!(magics.encode(blue), 0x8000);
+    }
+
+    #[test]
+    fn facet_colors_survive_binary() {
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut mesh = Mesh::new_from_parts(vec![zero; 3], vec![Facet { v1: 0, v2: 1, v3: 2, n: zero }; 2]);
+        mesh.facet_colors = vec![Color::rgb(1.0, 0.0, 0.0), Color::rgb(0.0, 1.0, 0.0)];
+        let mut w = MemWriter::new();
+        StlFile::write_binary(&mesh, &mut w).unwrap();
+        let read = StlFile::read_bytes(&w.into_inner()).unwrap().as_mesh();
+        assert_eq!(read.facet_colors, mesh.facet_colors);
+        assert_eq!(read.facet_attributes, vec![0x8000 | 31 << 10, 0x8000 | 31 << 5]);
+    }
+
+    #[test]
+    fn binary_with_solid_header_is_binary() {
+        let mut data = b"solid exported by a CAD tool".to_vec();
+        data.resize(80, 0);
+        data.push_all(&[1, 0, 0, 0]);
+        data.resize(84 + 50, 0);
+        let stl = StlFile::read_bytes(&data).unwrap();
+        assert_eq!(stl.format, StlFormat::Binary);
+        assert_eq!(stl.facets.len(), 1);
+    }
+
+    #[test]
+    fn declared_count_must_fit() {
+        let mut data = vec![0u8; 80];
+        data.push_all(&[3, 0, 0, 0]);
+        data.resize(84 + 2 * 50 + 10, 0);
+        let err = StlFile::read_bytes(&data).err().unwrap();
+        assert_eq!(err, parse_error(0, "header declares 3 triangles, but the file holds only 2".to_string()));
+    }
+
+    #[test]
+    fn parallel_decoding_keeps_order() {
+        let count = 3 * MIN_FACETS_PER_THREAD + 7;
+        let mut w = MemWriter::new();
+        for i in 0..count {
+            let v = Vector3D { x: i as f32, y: -(i as f32), z: 0.5 };
+            let f = StlFacet { n: v, v1: v, v2: v, v3: v, abc: i as u16 };
+            f.write(&mut w).unwrap();
+        }
+        let records = w.into_inner();
+        assert_eq!(decode_facets(&records, 4), decode_facets(&records, 1));
+        assert_eq!(decode_facets(&records, 4)[count - 1].abc, (count - 1) as u16);
+    }
+
+    #[test]
+    fn parallel_indexing_matches_one_thread() {
+        // A strip whose facets share vertices across the runs' seams.
+        let count = 3 * MIN_FACETS_PER_THREAD + 7;
+        let mut w = MemWriter::new();
+        let p = |i: usize| Vector3D { x: (i / 2) as f32, y: (i % 2) as f32, z: 0.0 };
+        for i in 0..count {
+            let f = StlFacet { n: p(0), v1: p(i), v2: p(i + 1), v3: p(i + 2), abc: i as u16 };
+            f.write(&mut w).unwrap();
+        }
+        let records = w.into_inner();
+        assert_eq!(decode_mesh(&records, 4), decode_mesh(&records, 1));
+        let (mesh, attributes) = decode_mesh(&records, 4);
+        assert_eq!(mesh.vertices.len(), count + 2);
+        assert_eq!(mesh.facets[count - 1].v3, count + 1);
+        assert_eq!(attributes[count - 1], (count - 1) as u16);
+    }
+
+    #[test]
+    fn mapped_read_matches_buffered() {
+        let path = Path::new("./Lily_Pad.stl");
+        let read = StlFile::read(&mut File::open(&path)).unwrap().as_mesh();
+        let mapped = StlFile::read_mapped(&path, 4).unwrap();
+        assert_eq!(mapped, read);
+    }
+
+    #[test]
+    fn facets_stream_through() {
+        let dir = TempDir::new("meshman").unwrap();
+        let path = dir.path().join("doubled.stl");
+        let input = File::open(&Path::new("./Lily_Pad.stl")).unwrap();
+        let reader = StlFacetReader::new(BufferedReader::new(input)).unwrap();
+        let declared = reader.count();
+        let mut writer = StlFacetWriter::new(File::create(&path).unwrap(), &reader.header).unwrap();
+        for f in reader {
+            let mut f = f.unwrap();
+            f.v1 = f.v1.scale(2.0);
+            f.v2 = f.v2.scale(2.0);
+            f.v3 = f.v3.scale(2.0);
+            writer.write(&f).unwrap();
+        }
+        writer.finish().unwrap();
+        let original = StlFile::read(&mut File::open(&Path::new("./Lily_Pad.stl")).unwrap()).unwrap();
+        let doubled = StlFile::read(&mut File::open(&path).unwrap()).unwrap();
+        assert_eq!(doubled.facets.len() as u32, declared);
+        assert_eq!(doubled.facets[7].v2, original.facets[7].v2.scale(2.0));
+    }
+
+    #[test]
+    fn streaming_a_truncated_file_fails_once() {
+        let mut data = vec![0u8; 80];
+        data.push_all(&[2, 0, 0, 0]);
+        data.resize(84 + 50 + 20, 0);
+        let mut reader = StlFacetReader::new(BufReader::new(&data)).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(reader.next().unwrap().err().unwrap(),
+                   parse_error(0, "file ends after 1 of 2 triangles".to_string()));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn streaming_refuses_ascii() {
+        let text = "solid part\n\
+                    facet normal 0 0 1\n\
+                      outer loop\n\
+                        vertex 0 0 0\n\
+                        vertex 1 0 0\n\
+                        vertex 0 1 0\n\
+                      endloop\n\
+                    endfacet\n\
+                    endsolid part\n";
+        let err = StlFacetReader::new(BufReader::new(text.as_bytes())).err().unwrap();
+        assert_eq!(err, parse_error(0, "input looks like an ASCII STL, which cannot be streamed".to_string()));
+    }
+
+    #[test]
+    fn indexing_is_deterministic() {
+        let path = Path::new("./Lily_Pad.stl");
+        let stl = StlFile::read(&mut File::open(&path).unwrap()).unwrap();
+        let mesh = stl.as_mesh();
+        assert_eq!(mesh.vertices[mesh.facets[0].v1], stl.facets[0].v1);
+        assert_eq!((mesh.facets[0].v1, mesh.facets[0].v2, mesh.facets[0].v3), (0, 1, 2));
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            let mut w = MemWriter::new();
+            OffFile::write(&StlFile::read_mapped(&path, 1).unwrap(), &mut w).unwrap();
+            outputs.push(w.into_inner());
+        }
+        assert!(outputs[0] == outputs[1] && outputs[1] == outputs[2]);
+    }
 }
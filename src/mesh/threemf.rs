@@ -0,0 +1,628 @@
+// 3MF package handling: an OPC zip holding the 3D/3dmodel.model XML part.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::old_io::{MemWriter,Reader,Writer};
+use std::str;
+use mesh::{Color,Facet,Mesh};
+use vector::Vector3D;
+use unit::Unit;
+use parse::parse_error;
+use xml::{Event,XmlReader,attr,escape};
+use zip;
+use zip::ZipEntry;
+use error::{MeshError,MeshResult};
+
+const MODEL_PATH: &'static str = "3D/3dmodel.model";
+const MODEL_REL: &'static str = "http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel";
+const CORE_NS: &'static str = "http://schemas.microsoft.com/3dmanufacturing/core/2015/02";
+
+// Affine transform in 3MF's "m00 m01 m02 m10 m11 m12 m20 m21 m22 m30 m31 m32"
+// order, applied to row vectors: the last three entries are the translation.
+pub type Transform = [f32; 12];
+
+pub const IDENTITY: Transform = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+
+pub struct ThreeMfFile {
+    pub unit: String,
+    pub metadata: Vec<(String, String)>,
+    pub materials: Vec<ThreeMfMaterials>,
+    pub objects: Vec<ThreeMfObject>,
+    pub items: Vec<ThreeMfItem>,
+}
+
+// A <basematerials> group; triangles refer to it by id and index.
+pub struct ThreeMfMaterials {
+    pub id: String,
+    pub bases: Vec<(String, Color)>,
+}
+
+// A material reference: property group id and index within it.
+pub type Property = (String, usize);
+
+pub struct ThreeMfObject {
+    pub id: String,
+    pub name: Option<String>,
+    pub property: Option<Property>,
+    pub vertices: Vec<Vector3D>,
+    pub triangles: Vec<Facet>,
+    pub triangle_properties: Vec<Option<Property>>,
+    pub components: Vec<ThreeMfItem>,
+}
+
+// A build item or component: an object placed with a transform.
+pub struct ThreeMfItem {
+    pub objectid: String,
+    pub transform: Transform,
+}
+
+impl ThreeMfFile {
+
+    pub fn read(r: &mut Reader) -> MeshResult<ThreeMfFile> {
+        let data = try!(r.read_to_end());
+        let entries = try!(zip::read_entries(&data));
+        let path = match zip::find(&entries, "_rels/.rels") {
+            Some(rels) => try!(model_path(&rels.data)),
+            None => MODEL_PATH.to_string(),
+        };
+        match zip::find(&entries, &path) {
+            Some(model) => ThreeMfFile::read_model(&model.data),
+            None => Err(parse_error(1, format!("3MF package has no {}", path))),
+        }
+    }
+
+    pub fn read_model(data: &[u8]) -> MeshResult<ThreeMfFile> {
+        let text = match str::from_utf8(data) {
+            Ok(t) => t,
+            Err(_) => return Err(parse_error(1, "3MF model is not valid UTF-8".to_string())),
+        };
+        let mut xml = XmlReader::new(text);
+        let attrs = match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if local(name) == "model" => attrs.clone(),
+            _ => return Err(parse_error(xml.line(), "expected <model> root element".to_string())),
+        };
+        let mut file = ThreeMfFile {
+            unit: attr(&attrs, "unit").unwrap_or("millimeter").to_string(),
+            metadata: Vec::new(),
+            materials: Vec::new(),
+            objects: Vec::new(),
+            items: Vec::new(),
+        };
+        loop {
+            match try!(xml.expect_event()) {
+                Event::Start(ref name, ref attrs) if local(name) == "metadata" => {
+                    let key = attr(attrs, "name").unwrap_or("").to_string();
+                    let value = try!(xml.read_text("metadata"));
+                    file.metadata.push((key, value.trim().to_string()));
+                },
+                Event::Start(ref name, _) if local(name) == "resources" => try!(read_resources(&mut xml, &mut file)),
+                Event::Start(ref name, _) if local(name) == "build" => try!(read_build(&mut xml, &mut file)),
+                Event::Start(..) => try!(xml.skip_element()),
+                Event::Text(_) => {},
+                Event::End(_) => break,
+            }
+        }
+        Ok(file)
+    }
+
+    pub fn object(&self, id: &str) -> Option<&ThreeMfObject> {
+        self.objects.iter().find(|o| o.id == id)
+    }
+
+    pub fn color(&self, p: &Property) -> Option<Color> {
+        self.materials.iter().find(|m| m.id == p.0)
+            .and_then(|m| m.bases.get(p.1))
+            .map(|b| b.1)
+    }
+
+    // Places every build item into one mesh, applying item and component
+    // transforms. Without a build, every object that is not a component of
+    // another is placed once.
+    pub fn as_mesh(&self) -> MeshResult<Mesh> {
+        let mut mesh = Mesh::new();
+        let mut colors = Vec::new();
+        if self.items.is_empty() {
+            for o in self.objects.iter() {
+                if self.objects.iter().any(|p| p.components.iter().any(|c| c.objectid == o.id)) {
+                    continue;
+                }
+                try!(self.add_object(&mut mesh, &mut colors, &o.id, IDENTITY, 0));
+            }
+        } else {
+            for item in self.items.iter() {
+                try!(self.add_object(&mut mesh, &mut colors, &item.objectid, item.transform, 0));
+            }
+        }
+        if colors.iter().any(|c: &Option<Color>| c.is_some()) {
+            mesh.facet_colors = colors.iter().map(|c| c.unwrap_or(Color::white())).collect();
+        }
+        mesh.unit = Unit::from_name(&self.unit);
+        mesh.calculate_normals();
+        Ok(mesh)
+    }
+
+    fn add_object(&self, mesh: &mut Mesh, colors: &mut Vec<Option<Color>>, id: &str,
+                  t: Transform, depth: usize) -> MeshResult<()> {
+        let object = match self.object(id) {
+            Some(o) => o,
+            None => return Err(parse_error(1, format!("reference to unknown object {}", id))),
+        };
+        if depth > 32 {
+            return Err(parse_error(1, format!("components of object {} nest too deeply", id)));
+        }
+        let base = mesh.vertices.len();
+        for v in object.vertices.iter() {
+            mesh.vertices.push(apply(&t, *v));
+        }
+        let default = object.property.as_ref().and_then(|p| self.color(p));
+        for (f, p) in object.triangles.iter().zip(object.triangle_properties.iter()) {
+            mesh.facets.push(Facet { v1: f.v1 + base, v2: f.v2 + base, v3: f.v3 + base, n: f.n });
+            colors.push(p.as_ref().and_then(|p| self.color(p)).or(default));
+        }
+        for c in object.components.iter() {
+            try!(self.add_object(mesh, colors, &c.objectid, compose(&c.transform, &t), depth + 1));
+        }
+        Ok(())
+    }
+
+    // Wraps a mesh as one object with one build item. Distinct facet colours
+    // become entries of a single base material group, which is also the
+    // object's default property as 3MF requires once triangles carry one.
+    // 3MF meshes need at least one triangle.
+    pub fn from_mesh(m: &Mesh) -> MeshResult<ThreeMfFile> {
+        if m.facets.is_empty() {
+            return Err(MeshError::InvalidMesh("3MF objects need at least one triangle".to_string()));
+        }
+        let mut bases: Vec<(String, Color)> = Vec::new();
+        let mut properties = Vec::with_capacity(m.facets.len());
+        for c in m.facet_colors.iter() {
+            let index = match bases.iter().position(|b| b.1 == *c) {
+                Some(i) => i,
+                None => {
+                    bases.push((format!("Material {}", bases.len() + 1), *c));
+                    bases.len() - 1
+                },
+            };
+            properties.push(Some(("2".to_string(), index)));
+        }
+        properties.resize(m.facets.len(), None);
+        let (materials, property) = if bases.is_empty() {
+            (Vec::new(), None)
+        } else {
+            (vec![ThreeMfMaterials { id: "2".to_string(), bases: bases }], Some(("2".to_string(), 0)))
+        };
+        Ok(ThreeMfFile {
+            unit: m.unit.map_or("millimeter", |u| u.name()).to_string(),
+            metadata: Vec::new(),
+            materials: materials,
+            objects: vec![ThreeMfObject {
+                id: "1".to_string(),
+                name: None,
+                property: property,
+                vertices: m.vertices.clone(),
+                triangles: m.facets.clone(),
+                triangle_properties: properties,
+                components: Vec::new(),
+            }],
+            items: vec![ThreeMfItem { objectid: "1".to_string(), transform: IDENTITY }],
+        })
+    }
+
+    pub fn write(&self, w: &mut Writer) -> MeshResult<()> {
+        let mut model = MemWriter::new();
+        try!(self.write_model(&mut model));
+        let entries = [
+            ZipEntry { name: "[Content_Types].xml".to_string(), data: CONTENT_TYPES.as_bytes().to_vec() },
+            ZipEntry { name: "_rels/.rels".to_string(), data: RELS.as_bytes().to_vec() },
+            ZipEntry { name: MODEL_PATH.to_string(), data: model.into_inner() },
+        ];
+        zip::write_entries(w, &entries, true)
+    }
+
+    pub fn write_model(&self, w: &mut Writer) -> MeshResult<()> {
+        try!(writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+        try!(writeln!(w, "<model unit=\"{}\" xml:lang=\"en-US\" xmlns=\"{}\">", escape(&self.unit), CORE_NS));
+        for &(ref key, ref value) in self.metadata.iter() {
+            try!(writeln!(w, "  <metadata name=\"{}\">{}</metadata>", escape(key), escape(value)));
+        }
+        try!(writeln!(w, "  <resources>"));
+        for m in self.materials.iter() {
+            try!(writeln!(w, "    <basematerials id=\"{}\">", escape(&m.id)));
+            for &(ref name, c) in m.bases.iter() {
+                let b = c.to_bytes();
+                try!(writeln!(w, "      <base name=\"{}\" displaycolor=\"#{:02X}{:02X}{:02X}{:02X}\" />",
+                              escape(name), b[0], b[1], b[2], b[3]));
+            }
+            try!(writeln!(w, "    </basematerials>"));
+        }
+        for o in self.objects.iter() {
+            try!(write!(w, "    <object id=\"{}\" type=\"model\"", escape(&o.id)));
+            if let Some(ref name) = o.name {
+                try!(write!(w, " name=\"{}\"", escape(name)));
+            }
+            if let Some((ref pid, pindex)) = o.property {
+                try!(write!(w, " pid=\"{}\" pindex=\"{}\"", escape(pid), pindex));
+            }
+            try!(writeln!(w, ">"));
+            if !o.triangles.is_empty() {
+                try!(writeln!(w, "      <mesh>"));
+                try!(writeln!(w, "        <vertices>"));
+                for v in o.vertices.iter() {
+                    try!(writeln!(w, "          <vertex x=\"{}\" y=\"{}\" z=\"{}\" />", v.x, v.y, v.z));
+                }
+                try!(writeln!(w, "        </vertices>"));
+                try!(writeln!(w, "        <triangles>"));
+                for (t, p) in o.triangles.iter().zip(o.triangle_properties.iter()) {
+                    try!(write!(w, "          <triangle v1=\"{}\" v2=\"{}\" v3=\"{}\"", t.v1, t.v2, t.v3));
+                    if let Some((ref pid, p1)) = *p {
+                        try!(write!(w, " pid=\"{}\" p1=\"{}\"", escape(pid), p1));
+                    }
+                    try!(writeln!(w, " />"));
+                }
+                try!(writeln!(w, "        </triangles>"));
+                try!(writeln!(w, "      </mesh>"));
+            }
+            if !o.components.is_empty() {
+                try!(writeln!(w, "      <components>"));
+                for c in o.components.iter() {
+                    try!(writeln!(w, "        <component objectid=\"{}\" transform=\"{}\" />",
+                                  escape(&c.objectid), transform_str(&c.transform)));
+                }
+                try!(writeln!(w, "      </components>"));
+            }
+            try!(writeln!(w, "    </object>"));
+        }
+        try!(writeln!(w, "  </resources>"));
+        try!(writeln!(w, "  <build>"));
+        for item in self.items.iter() {
+            try!(writeln!(w, "    <item objectid=\"{}\" transform=\"{}\" />",
+                          escape(&item.objectid), transform_str(&item.transform)));
+        }
+        try!(writeln!(w, "  </build>"));
+        try!(writeln!(w, "</model>"));
+        Ok(())
+    }
+}
+
+const CONTENT_TYPES: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">
+  <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\" />
+  <Default Extension=\"model\" ContentType=\"application/vnd.ms-package.3dmanufacturing-3dmodel+xml\" />
+</Types>
+";
+
+const RELS: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">
+  <Relationship Target=\"/3D/3dmodel.model\" Id=\"rel0\" Type=\"http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel\" />
+</Relationships>
+";
+
+// Element name without any namespace prefix.
+fn local(name: &str) -> &str {
+    match name.rfind(':') {
+        Some(i) => &name[i + 1..],
+        None => name,
+    }
+}
+
+// Finds the model part named by the package relationships.
+fn model_path(rels: &[u8]) -> MeshResult<String> {
+    let text = match str::from_utf8(rels) {
+        Ok(t) => t,
+        Err(_) => return Err(parse_error(1, "_rels/.rels is not valid UTF-8".to_string())),
+    };
+    let mut xml = XmlReader::new(text);
+    while let Some(e) = try!(xml.next_event()) {
+        if let Event::Start(ref name, ref attrs) = e {
+            if local(name) == "Relationship" && attr(attrs, "Type") == Some(MODEL_REL) {
+                if let Some(target) = attr(attrs, "Target") {
+                    return Ok(target.trim_left_matches('/').to_string());
+                }
+            }
+        }
+    }
+    Ok(MODEL_PATH.to_string())
+}
+
+fn read_resources(xml: &mut XmlReader, file: &mut ThreeMfFile) -> MeshResult<()> {
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if local(name) == "basematerials" => {
+                let id = attr(attrs, "id").unwrap_or("").to_string();
+                let materials = try!(read_base_materials(xml, id));
+                file.materials.push(materials);
+            },
+            Event::Start(ref name, ref attrs) if local(name) == "object" => {
+                let object = try!(read_object(xml, attrs));
+                file.objects.push(object);
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(()),
+        }
+    }
+}
+
+fn read_base_materials(xml: &mut XmlReader, id: String) -> MeshResult<ThreeMfMaterials> {
+    let mut materials = ThreeMfMaterials { id: id, bases: Vec::new() };
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if local(name) == "base" => {
+                let color = match attr(attrs, "displaycolor").and_then(parse_color) {
+                    Some(c) => c,
+                    None => return Err(parse_error(xml.line(), "<base> needs a #RRGGBB displaycolor".to_string())),
+                };
+                materials.bases.push((attr(attrs, "name").unwrap_or("").to_string(), color));
+                try!(xml.skip_element());
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(materials),
+        }
+    }
+}
+
+fn read_object(xml: &mut XmlReader, attrs: &[(String, String)]) -> MeshResult<ThreeMfObject> {
+    let mut object = ThreeMfObject {
+        id: attr(attrs, "id").unwrap_or("").to_string(),
+        name: attr(attrs, "name").map(|n| n.to_string()),
+        property: try!(property(xml, attr(attrs, "pid"), attr(attrs, "pindex"))),
+        vertices: Vec::new(),
+        triangles: Vec::new(),
+        triangle_properties: Vec::new(),
+        components: Vec::new(),
+    };
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, _) if local(name) == "mesh" => try!(read_mesh(xml, &mut object)),
+            Event::Start(ref name, _) if local(name) == "components" => {
+                loop {
+                    match try!(xml.expect_event()) {
+                        Event::Start(ref name, ref attrs) if local(name) == "component" => {
+                            let c = try!(read_item(xml, attrs));
+                            object.components.push(c);
+                        },
+                        Event::Start(..) => try!(xml.skip_element()),
+                        Event::Text(_) => {},
+                        Event::End(_) => break,
+                    }
+                }
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(object),
+        }
+    }
+}
+
+fn read_mesh(xml: &mut XmlReader, object: &mut ThreeMfObject) -> MeshResult<()> {
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if local(name) == "vertex" => {
+                let mut c = [0.0f32; 3];
+                for (i, key) in ["x", "y", "z"].iter().enumerate() {
+                    c[i] = match attr(attrs, *key).and_then(|v| v.parse::<f32>().ok()) {
+                        Some(v) => v,
+                        None => return Err(parse_error(xml.line(), format!("<vertex> has no valid {}", key))),
+                    };
+                }
+                object.vertices.push(Vector3D { x: c[0], y: c[1], z: c[2] });
+                try!(xml.skip_element());
+            },
+            Event::Start(ref name, ref attrs) if local(name) == "triangle" => {
+                let mut v = [0usize; 3];
+                for (i, key) in ["v1", "v2", "v3"].iter().enumerate() {
+                    v[i] = match attr(attrs, *key).and_then(|v| v.parse::<usize>().ok()) {
+                        Some(v) => v,
+                        None => return Err(parse_error(xml.line(), format!("<triangle> has no valid {}", key))),
+                    };
+                    if v[i] >= object.vertices.len() {
+                        return Err(parse_error(xml.line(),
+                            format!("vertex index {} out of range ({} vertices)", v[i], object.vertices.len())));
+                    }
+                }
+                let pid = attr(attrs, "pid").or(object.property.as_ref().map(|p| &p.0[..]));
+                let p = try!(property(xml, pid, attr(attrs, "p1")));
+                object.triangles.push(Facet { v1: v[0], v2: v[1], v3: v[2], n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } });
+                object.triangle_properties.push(p);
+                try!(xml.skip_element());
+            },
+            // <vertices> and <triangles> are just containers.
+            Event::Start(ref name, _) if local(name) == "vertices" || local(name) == "triangles" => {},
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(ref name) if local(name) == "vertices" || local(name) == "triangles" => {},
+            Event::End(_) => return Ok(()),
+        }
+    }
+}
+
+fn read_build(xml: &mut XmlReader, file: &mut ThreeMfFile) -> MeshResult<()> {
+    loop {
+        match try!(xml.expect_event()) {
+            Event::Start(ref name, ref attrs) if local(name) == "item" => {
+                let item = try!(read_item(xml, attrs));
+                file.items.push(item);
+            },
+            Event::Start(..) => try!(xml.skip_element()),
+            Event::Text(_) => {},
+            Event::End(_) => return Ok(()),
+        }
+    }
+}
+
+fn read_item(xml: &mut XmlReader, attrs: &[(String, String)]) -> MeshResult<ThreeMfItem> {
+    let objectid = match attr(attrs, "objectid") {
+        Some(id) => id.to_string(),
+        None => return Err(parse_error(xml.line(), "missing objectid".to_string())),
+    };
+    let transform = match attr(attrs, "transform") {
+        Some(t) => match parse_transform(t) {
+            Some(t) => t,
+            None => return Err(parse_error(xml.line(), format!("invalid transform '{}'", t))),
+        },
+        None => IDENTITY,
+    };
+    try!(xml.skip_element());
+    Ok(ThreeMfItem { objectid: objectid, transform: transform })
+}
+
+fn property(xml: &XmlReader, pid: Option<&str>, index: Option<&str>) -> MeshResult<Option<Property>> {
+    match (pid, index) {
+        (Some(pid), Some(index)) => match index.parse::<usize>() {
+            Ok(i) => Ok(Some((pid.to_string(), i))),
+            Err(_) => Err(parse_error(xml.line(), format!("invalid property index '{}'", index))),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    if !s.starts_with("#") || (s.len() != 7 && s.len() != 9) {
+        return None;
+    }
+    let byte = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+    let alpha = if s.len() == 9 { byte(7) } else { Some(255) };
+    match (byte(1), byte(3), byte(5), alpha) {
+        (Some(r), Some(g), Some(b), Some(a)) => Some(Color::from_bytes(r, g, b, a)),
+        _ => None,
+    }
+}
+
+fn parse_transform(s: &str) -> Option<Transform> {
+    let values: Vec<f32> = s.split(|c: char| c.is_whitespace()).filter(|w| !w.is_empty())
+                            .filter_map(|w| w.parse::<f32>().ok()).collect();
+    if values.len() != 12 {
+        return None;
+    }
+    let mut t = IDENTITY;
+    for i in 0..12 {
+        t[i] = values[i];
+    }
+    Some(t)
+}
+
+fn transform_str(t: &Transform) -> String {
+    let parts: Vec<String> = t.iter().map(|v| format!("{}", v)).collect();
+    parts.connect(" ")
+}
+
+fn apply(t: &Transform, v: Vector3D) -> Vector3D {
+    Vector3D {
+        x: v.x * t[0] + v.y * t[3] + v.z * t[6] + t[9],
+        y: v.x * t[1] + v.y * t[4] + v.z * t[7] + t[10],
+        z: v.x * t[2] + v.y * t[5] + v.z * t[8] + t[11],
+    }
+}
+
+// The transform that applies `first`, then `second`.
+fn compose(first: &Transform, second: &Transform) -> Transform {
+    let mut t = [0.0f32; 12];
+    for row in 0..4 {
+        for col in 0..3 {
+            let mut sum = if row == 3 { second[9 + col] } else { 0.0 };
+            for k in 0..3 {
+                sum += first[row * 3 + k] * second[k * 3 + col];
+            }
+            t[row * 3 + col] = sum;
+        }
+    }
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Color,Mesh};
+    use error::MeshError;
+    use std::old_io::{BufReader,MemWriter};
+
+    const MODEL: &'static str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<model unit=\"inch\" xmlns=\"http://schemas.microsoft.com/3dmanufacturing/core/2015/02\">
+  <metadata name=\"Title\">Two parts</metadata>
+  <resources>
+    <basematerials id=\"5\">
+      <base name=\"Red\" displaycolor=\"#FF0000\" />
+      <base name=\"Blue\" displaycolor=\"#0000FFFF\" />
+    </basematerials>
+    <object id=\"1\" type=\"model\" pid=\"5\" pindex=\"0\">
+      <mesh>
+        <vertices>
+          <vertex x=\"0\" y=\"0\" z=\"0\" />
+          <vertex x=\"1\" y=\"0\" z=\"0\" />
+          <vertex x=\"0\" y=\"1\" z=\"0\" />
+        </vertices>
+        <triangles>
+          <triangle v1=\"0\" v2=\"1\" v3=\"2\" />
+          <triangle v1=\"2\" v2=\"1\" v3=\"0\" p1=\"1\" />
+        </triangles>
+      </mesh>
+    </object>
+  </resources>
+  <build>
+    <item objectid=\"1\" />
+    <item objectid=\"1\" transform=\"1 0 0 0 1 0 0 0 1 10 0 0\" />
+  </build>
+</model>
+";
+
+    #[test]
+    fn reads_items_transforms_and_materials() {
+        let file = ThreeMfFile::read_model(MODEL.as_bytes()).unwrap();
+        assert_eq!(file.unit, "inch");
+        assert_eq!(file.metadata, vec![("Title".to_string(), "Two parts".to_string())]);
+        assert_eq!(file.items.len(), 2);
+
+        let mesh = file.as_mesh().unwrap();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.facets.len(), 4);
+        assert_eq!(mesh.vertices[4].x, 11.0);
+        assert_eq!(mesh.facet_colors[0], Color::rgb(1.0, 0.0, 0.0));
+        assert_eq!(mesh.facet_colors[3], Color::rgb(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn package_round_trip() {
+        let mesh = ThreeMfFile::read_model(MODEL.as_bytes()).unwrap().as_mesh().unwrap();
+        let mut w = MemWriter::new();
+        ThreeMfFile::from_mesh(&mesh).unwrap().write(&mut w).unwrap();
+        let data = w.into_inner();
+        let file = ThreeMfFile::read(&mut BufReader::new(&data)).unwrap();
+        assert_eq!(file.materials[0].bases.len(), 2);
+        assert_eq!(file.objects[0].property, Some(("2".to_string(), 0)));
+        assert_eq!(file.as_mesh().unwrap(), mesh);
+    }
+
+    #[test]
+    fn empty_meshes_are_not_written() {
+        let err = ThreeMfFile::from_mesh(&Mesh::new()).err().unwrap();
+        assert_eq!(err, MeshError::InvalidMesh("3MF objects need at least one triangle".to_string()));
+    }
+
+    #[test]
+    fn without_a_build_components_are_placed_once() {
+        let model = MODEL.replace("  <build>
+    <item objectid=\"1\" />
+    <item objectid=\"1\" transform=\"1 0 0 0 1 0 0 0 1 10 0 0\" />
+  </build>
+", "").replace("  </resources>", "    <object id=\"3\" type=\"model\">
+      <components>
+        <component objectid=\"1\" transform=\"1 0 0 0 1 0 0 0 1 0 0 5\" />
+      </components>
+    </object>
+  </resources>");
+        let file = ThreeMfFile::read_model(model.as_bytes()).unwrap();
+        assert!(file.items.is_empty());
+        let mesh = file.as_mesh().unwrap();
+        assert_eq!(mesh.facets.len(), 2);
+        assert!(mesh.vertices.iter().all(|v| v.z == 5.0));
+    }
+
+    #[test]
+    fn transforms_compose() {
+        let scale = [2.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0];
+        let shift = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 2.0, 3.0];
+        let v = super::apply(&super::compose(&scale, &shift), ::vector::Vector3D { x: 1.0, y: 1.0, z: 1.0 });
+        assert_eq!((v.x, v.y, v.z), (3.0, 4.0, 5.0));
+    }
+}
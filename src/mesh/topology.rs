@@ -0,0 +1,336 @@
+// Connectivity for a mesh: half-edges, the edges they lie on, and the
+// queries repair and analysis code needs on top of them.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::collections::{HashMap,HashSet};
+use mesh::Mesh;
+use error::MeshResult;
+
+// One side of a facet edge. Half-edge 3 * f + k runs from corner k of facet f
+// to corner k + 1, so the facet and next half-edge follow from the index.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct HalfEdge {
+    pub origin: usize,
+    pub target: usize,
+    pub facet: usize,
+    pub next: usize,
+    // The opposite half-edge, when exactly one facet lies on the other side
+    // and runs the edge the other way.
+    pub twin: Option<usize>,
+    pub edge: usize,
+}
+
+// An undirected edge with every half-edge that lies on it. One half-edge
+// makes it a boundary edge, more than two a non-manifold one.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Edge {
+    pub a: usize,
+    pub b: usize,
+    pub half_edges: Vec<usize>,
+}
+
+// Owns the mesh it was built from, so the connectivity cannot go stale;
+// into_mesh() hands the mesh back unchanged. Edges are numbered in the order
+// the facets first use them.
+pub struct Topology {
+    mesh: Mesh,
+    half_edges: Vec<HalfEdge>,
+    edges: Vec<Edge>,
+    edge_index: HashMap<(usize, usize), usize>,
+    // Half-edges leaving each vertex, in facet order.
+    outgoing: Vec<Vec<usize>>,
+}
+
+impl Topology {
+    pub fn new(mesh: Mesh) -> MeshResult<Topology> {
+        try!(mesh.check_indices());
+        let mut topo = Topology {
+            half_edges: Vec::with_capacity(3 * mesh.facets.len()),
+            edges: Vec::new(),
+            edge_index: HashMap::new(),
+            outgoing: vec![Vec::new(); mesh.vertices.len()],
+            mesh: mesh,
+        };
+        for (f, facet) in topo.mesh.facets.iter().enumerate() {
+            let corners = [facet.v1, facet.v2, facet.v3];
+            for k in 0..3 {
+                let (a, b) = (corners[k], corners[(k + 1) % 3]);
+                let key = if a < b { (a, b) } else { (b, a) };
+                let he = 3 * f + k;
+                let edge = match topo.edge_index.get(&key) {
+                    Some(&e) => e,
+                    None => {
+                        topo.edges.push(Edge { a: key.0, b: key.1, half_edges: Vec::new() });
+                        topo.edges.len() - 1
+                    },
+                };
+                topo.edge_index.insert(key, edge);
+                topo.edges[edge].half_edges.push(he);
+                topo.outgoing[a].push(he);
+                topo.half_edges.push(HalfEdge {
+                    origin: a,
+                    target: b,
+                    facet: f,
+                    next: 3 * f + (k + 1) % 3,
+                    twin: None,
+                    edge: edge,
+                });
+            }
+        }
+        for e in topo.edges.iter() {
+            if e.half_edges.len() == 2 {
+                let (h, g) = (e.half_edges[0], e.half_edges[1]);
+                if topo.half_edges[h].origin == topo.half_edges[g].target &&
+                   topo.half_edges[h].target == topo.half_edges[g].origin {
+                    topo.half_edges[h].twin = Some(g);
+                    topo.half_edges[g].twin = Some(h);
+                }
+            }
+        }
+        Ok(topo)
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        self.mesh
+    }
+
+    pub fn half_edges(&self) -> &[HalfEdge] {
+        &self.half_edges
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    // The edge between two vertices, in either direction.
+    pub fn edge_between(&self, a: usize, b: usize) -> Option<&Edge> {
+        let key = if a < b { (a, b) } else { (b, a) };
+        self.edge_index.get(&key).map(|&e| &self.edges[e])
+    }
+
+    // The facets on the edge between two vertices; empty if there is none.
+    pub fn edge_facets(&self, a: usize, b: usize) -> Vec<usize> {
+        match self.edge_between(a, b) {
+            Some(e) => e.half_edges.iter().map(|&h| self.half_edges[h].facet).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Facets sharing an edge with `f`, in the order of its edges, each once.
+    pub fn facet_neighbours(&self, f: usize) -> Vec<usize> {
+        let mut neighbours = Vec::new();
+        for he in 3 * f..3 * f + 3 {
+            for &h in self.edges[self.half_edges[he].edge].half_edges.iter() {
+                let g = self.half_edges[h].facet;
+                if g != f && !neighbours.contains(&g) {
+                    neighbours.push(g);
+                }
+            }
+        }
+        neighbours
+    }
+
+    // Vertices joined to `v` by an edge, each once, in the order the facets
+    // around `v` reach them.
+    pub fn one_ring(&self, v: usize) -> Vec<usize> {
+        let mut ring = Vec::new();
+        for &h in self.outgoing[v].iter() {
+            let he = &self.half_edges[h];
+            let prev = &self.half_edges[self.half_edges[he.next].next];
+            for &w in [he.target, prev.origin].iter() {
+                if w != v && !ring.contains(&w) {
+                    ring.push(w);
+                }
+            }
+        }
+        ring
+    }
+
+    // Facets using `v` as a corner, in facet order.
+    pub fn vertex_facets(&self, v: usize) -> Vec<usize> {
+        self.outgoing[v].iter().map(|&h| self.half_edges[h].facet).collect()
+    }
+
+    // Half-edges no other facet borders, as (from, to) in their facet's
+    // winding.
+    pub fn boundary_edges(&self) -> Vec<(usize, usize)> {
+        self.edges.iter()
+            .filter(|e| e.half_edges.len() == 1)
+            .map(|e| {
+                let he = &self.half_edges[e.half_edges[0]];
+                (he.origin, he.target)
+            })
+            .collect()
+    }
+
+    // Chains of boundary half-edges, each as the vertices it passes in
+    // order. Around a hole the chain closes, and the last vertex leads back
+    // to the first. Where boundaries touch at a vertex a chain closes as soon
+    // as it can, and otherwise goes on along the first unused half-edge.
+    pub fn boundary_loops(&self) -> Vec<Vec<usize>> {
+        let boundary: Vec<usize> = self.edges.iter()
+            .filter(|e| e.half_edges.len() == 1)
+            .map(|e| e.half_edges[0])
+            .collect();
+        let mut leaving: HashMap<usize, Vec<usize>> = HashMap::new();
+        for &h in boundary.iter() {
+            leaving.entry(self.half_edges[h].origin).get().unwrap_or_else(|e| e.insert(Vec::new())).push(h);
+        }
+        let mut used: HashSet<usize> = HashSet::new();
+        let mut loops = Vec::new();
+        for &start in boundary.iter() {
+            if used.contains(&start) {
+                continue;
+            }
+            let mut chain = Vec::new();
+            let mut h = start;
+            loop {
+                used.insert(h);
+                chain.push(self.half_edges[h].origin);
+                let target = self.half_edges[h].target;
+                if target == chain[0] {
+                    break;
+                }
+                let next = leaving.get(&target)
+                    .and_then(|hs| hs.iter().find(|g| !used.contains(*g)).map(|g| *g));
+                match next {
+                    Some(g) => h = g,
+                    None => break,
+                }
+            }
+            loops.push(chain);
+        }
+        loops
+    }
+
+    // Vertices whose facets do not form a single fan joined through edges
+    // at the vertex, such as the tips of two cones touching.
+    pub fn non_manifold_vertices(&self) -> Vec<usize> {
+        (0..self.outgoing.len()).filter(|&v| self.fans(v) > 1).collect()
+    }
+
+    // How many groups the facets around `v` fall into when facets sharing
+    // an edge at `v` are grouped together.
+    fn fans(&self, v: usize) -> usize {
+        let facets = self.vertex_facets(v);
+        let mut group: Vec<usize> = (0..facets.len()).collect();
+        fn root(group: &mut Vec<usize>, mut i: usize) -> usize {
+            while group[i] != i {
+                group[i] = group[group[i]];
+                i = group[i];
+            }
+            i
+        }
+        for (i, &h) in self.outgoing[v].iter().enumerate() {
+            let he = &self.half_edges[h];
+            let prev = self.half_edges[he.next].next;
+            for &e in [he.edge, self.half_edges[prev].edge].iter() {
+                for &g in self.edges[e].half_edges.iter() {
+                    let other = self.half_edges[g].facet;
+                    if let Some(j) = facets.iter().position(|&f| f == other) {
+                        let (ri, rj) = (root(&mut group, i), root(&mut group, j));
+                        group[ri] = rj;
+                    }
+                }
+            }
+        }
+        (0..facets.len()).filter(|&i| root(&mut group, i) == i).count()
+    }
+
+    // For each facet, the shell it belongs to: facets joined through edges
+    // share one. Shells are numbered in the order of their first facet.
+    pub fn shells(&self) -> Vec<usize> {
+        let none = self.mesh.facets.len();
+        let mut shell = vec![none; none];
+        let mut count = 0;
+        for start in 0..none {
+            if shell[start] != none {
+                continue;
+            }
+            shell[start] = count;
+            let mut stack = vec![start];
+            while let Some(f) = stack.pop() {
+                for g in self.facet_neighbours(f).into_iter() {
+                    if shell[g] == none {
+                        shell[g] = count;
+                        stack.push(g);
+                    }
+                }
+            }
+            count += 1;
+        }
+        shell
+    }
+
+    // Edges shared by more than two facets.
+    pub fn non_manifold_edges(&self) -> Vec<(usize, usize)> {
+        self.edges.iter()
+            .filter(|e| e.half_edges.len() > 2)
+            .map(|e| (e.a, e.b))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Facet,Mesh};
+    use vector::Vector3D;
+
+    fn f(a: usize, b: usize, c: usize) -> Facet {
+        Facet { v1: a, v2: b, v3: c, n: Vector3D { x: 0.0, y: 0.0, z: 1.0 } }
+    }
+
+    // Two triangles making the unit square, split along 0-2.
+    fn square() -> Mesh {
+        let v = |x: f32, y: f32| Vector3D { x: x, y: y, z: 0.0 };
+        Mesh::new_from_parts(vec![v(0.0, 0.0), v(1.0, 0.0), v(1.0, 1.0), v(0.0, 1.0)],
+                             vec![f(0, 1, 2), f(0, 2, 3)])
+    }
+
+    #[test]
+    fn square_connectivity() {
+        let topo = Topology::new(square()).unwrap();
+        assert_eq!(topo.edges().len(), 5);
+        assert_eq!(topo.facet_neighbours(0), vec![1]);
+        assert_eq!(topo.edge_facets(2, 0), vec![0, 1]);
+        assert!(topo.edge_facets(1, 3).is_empty());
+        assert_eq!(topo.one_ring(0), vec![1, 2, 3]);
+        assert_eq!(topo.one_ring(1), vec![2, 0]);
+        assert_eq!(topo.boundary_edges(), vec![(0, 1), (1, 2), (2, 3), (3, 0)]);
+        let diagonal = topo.half_edges()[2];
+        assert_eq!((diagonal.origin, diagonal.target), (2, 0));
+        assert_eq!(diagonal.twin, Some(3));
+        assert_eq!(topo.boundary_loops(), vec![vec![0, 1, 2, 3]]);
+        assert!(topo.non_manifold_vertices().is_empty());
+        assert_eq!(topo.shells(), vec![0, 0]);
+        assert_eq!(topo.into_mesh(), square());
+    }
+
+    #[test]
+    fn fins_are_non_manifold() {
+        let mut mesh = square();
+        mesh.vertices.push(Vector3D { x: 0.5, y: 0.5, z: 1.0 });
+        mesh.facets.push(f(0, 2, 4));
+        let topo = Topology::new(mesh).unwrap();
+        assert_eq!(topo.non_manifold_edges(), vec![(0, 2)]);
+        assert_eq!(topo.half_edges()[2].twin, None);
+        assert_eq!(topo.facet_neighbours(2), vec![0, 1]);
+    }
+
+    #[test]
+    fn touching_corners_are_non_manifold() {
+        let v = |x: f32, y: f32| Vector3D { x: x, y: y, z: 0.0 };
+        let mesh = Mesh::new_from_parts(vec![v(0.0, 0.0), v(1.0, 0.0), v(0.0, 1.0), v(-1.0, 0.0), v(0.0, -1.0)],
+                                        vec![f(0, 1, 2), f(0, 3, 4)]);
+        let topo = Topology::new(mesh).unwrap();
+        assert_eq!(topo.non_manifold_vertices(), vec![0]);
+        assert_eq!(topo.boundary_loops().len(), 2);
+        assert_eq!(topo.shells(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,95 @@
+// Units of length that mesh formats declare.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::fmt;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Unit {
+    Micron,
+    Millimeter,
+    Centimeter,
+    Meter,
+    Inch,
+    Foot,
+}
+
+impl Unit {
+    // Accepts the spellings AMF and 3MF use, and a few common abbreviations.
+    pub fn from_name(name: &str) -> Option<Unit> {
+        match &name.trim().to_lowercase()[..] {
+            "micron" | "micrometer" | "um" => Some(Unit::Micron),
+            "millimeter" | "millimetre" | "mm" => Some(Unit::Millimeter),
+            "centimeter" | "centimetre" | "cm" => Some(Unit::Centimeter),
+            "meter" | "metre" | "m" => Some(Unit::Meter),
+            "inch" | "in" => Some(Unit::Inch),
+            "foot" | "feet" | "ft" => Some(Unit::Foot),
+            _ => None,
+        }
+    }
+
+    // The name 3MF uses, which is also AMF's for every unit but feet.
+    // AMF has no centimetres at all.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Unit::Micron => "micron",
+            Unit::Millimeter => "millimeter",
+            Unit::Centimeter => "centimeter",
+            Unit::Meter => "meter",
+            Unit::Inch => "inch",
+            Unit::Foot => "foot",
+        }
+    }
+
+    // Millimetres are the base because the common factors are exact in them.
+    pub fn millimeters(&self) -> f64 {
+        match *self {
+            Unit::Micron => 0.001,
+            Unit::Millimeter => 1.0,
+            Unit::Centimeter => 10.0,
+            Unit::Meter => 1000.0,
+            Unit::Inch => 25.4,
+            Unit::Foot => 304.8,
+        }
+    }
+
+    // What a length in this unit is multiplied by to express it in `to`.
+    pub fn factor_to(&self, to: Unit) -> f64 {
+        self.millimeters() / to.millimeters()
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Facet,Mesh};
+    use vector::Vector3D;
+
+    #[test]
+    fn names_round_trip() {
+        for u in [Unit::Micron, Unit::Millimeter, Unit::Centimeter, Unit::Meter, Unit::Inch, Unit::Foot].iter() {
+            assert_eq!(Unit::from_name(u.name()), Some(*u));
+        }
+        assert_eq!(Unit::from_name("Feet"), Some(Unit::Foot));
+        assert_eq!(Unit::from_name("furlong"), None);
+        assert_eq!(Unit::Inch.factor_to(Unit::Millimeter), 25.4);
+    }
+
+    #[test]
+    fn converting_rescales_vertices() {
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut mesh = Mesh::new_from_parts(vec![zero, Vector3D { x: 1.0, y: 2.0, z: -0.5 }],
+                                            vec![Facet { v1: 0, v2: 1, v3: 1, n: zero }]);
+        assert!(mesh.convert_units(Unit::Millimeter).is_err());
+        mesh.unit = Some(Unit::Inch);
+        mesh.convert_units(Unit::Millimeter).unwrap();
+        assert_eq!(mesh.unit, Some(Unit::Millimeter));
+        assert_eq!(mesh.vertices[1], Vector3D { x: 25.4, y: 50.8, z: -12.7 });
+    }
+}
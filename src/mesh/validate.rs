@@ -0,0 +1,196 @@
+// Checks a mesh needs to pass before it can be printed.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::collections::HashMap;
+use std::f32;
+use mesh::Mesh;
+use topology::Topology;
+use vector::Vector3D;
+
+// Everything validate() found. Vertex pairs are edges, single numbers are
+// vertex or facet indices. When a facet has an out of range index the
+// connectivity cannot be built, and only the index and facet checks run.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ValidationReport {
+    pub vertices: usize,
+    pub facets: usize,
+    // (facet, index) for every corner that names a missing vertex.
+    pub invalid_indices: Vec<(usize, usize)>,
+    pub non_manifold_edges: Vec<(usize, usize)>,
+    pub non_manifold_vertices: Vec<usize>,
+    pub boundary_edges: Vec<(usize, usize)>,
+    // Each boundary loop, as the vertices around it.
+    pub holes: Vec<Vec<usize>>,
+    // Pairs of facets that run their shared edge the same way, so one of
+    // them faces the wrong side.
+    pub inconsistent_facets: Vec<(usize, usize)>,
+    // Facets with a repeated corner or no area to speak of.
+    pub degenerate_facets: Vec<usize>,
+    // Facets with the same corners as an earlier one, in any order.
+    pub duplicate_facets: Vec<usize>,
+}
+
+impl ValidationReport {
+    pub fn is_watertight(&self) -> bool {
+        self.invalid_indices.is_empty() && self.boundary_edges.is_empty() && self.non_manifold_edges.is_empty()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.is_watertight() &&
+        self.non_manifold_vertices.is_empty() &&
+        self.inconsistent_facets.is_empty() &&
+        self.degenerate_facets.is_empty() &&
+        self.duplicate_facets.is_empty()
+    }
+
+    // The report as a single JSON object.
+    pub fn to_json(&self) -> String {
+        fn pairs(v: &[(usize, usize)]) -> String {
+            let items: Vec<String> = v.iter().map(|&(a, b)| format!("[{},{}]", a, b)).collect();
+            format!("[{}]", items.connect(","))
+        }
+        fn list(v: &[usize]) -> String {
+            let items: Vec<String> = v.iter().map(|i| i.to_string()).collect();
+            format!("[{}]", items.connect(","))
+        }
+        let holes: Vec<String> = self.holes.iter().map(|h| list(h)).collect();
+        format!("{{\"valid\":{},\"watertight\":{},\"vertices\":{},\"facets\":{},\
+                 \"invalid_indices\":{},\"non_manifold_edges\":{},\"non_manifold_vertices\":{},\
+                 \"boundary_edges\":{},\"holes\":[{}],\"inconsistent_facets\":{},\
+                 \"degenerate_facets\":{},\"duplicate_facets\":{}}}",
+                self.is_valid(), self.is_watertight(), self.vertices, self.facets,
+                pairs(&self.invalid_indices), pairs(&self.non_manifold_edges), list(&self.non_manifold_vertices),
+                pairs(&self.boundary_edges), holes.connect(","), pairs(&self.inconsistent_facets),
+                list(&self.degenerate_facets), list(&self.duplicate_facets))
+    }
+}
+
+pub fn validate(mesh: &Mesh) -> ValidationReport {
+    let mut report = ValidationReport {
+        vertices: mesh.vertices.len(),
+        facets: mesh.facets.len(),
+        invalid_indices: Vec::new(),
+        non_manifold_edges: Vec::new(),
+        non_manifold_vertices: Vec::new(),
+        boundary_edges: Vec::new(),
+        holes: Vec::new(),
+        inconsistent_facets: Vec::new(),
+        degenerate_facets: Vec::new(),
+        duplicate_facets: Vec::new(),
+    };
+    for (i, f) in mesh.facets.iter().enumerate() {
+        for &v in [f.v1, f.v2, f.v3].iter() {
+            if v >= mesh.vertices.len() {
+                report.invalid_indices.push((i, v));
+            }
+        }
+    }
+    let mut seen: HashMap<[usize; 3], usize> = HashMap::new();
+    for (i, f) in mesh.facets.iter().enumerate() {
+        let mut key = [f.v1, f.v2, f.v3];
+        key.sort();
+        if seen.contains_key(&key) {
+            report.duplicate_facets.push(i);
+        } else {
+            seen.insert(key, i);
+        }
+        let n = mesh.vertices.len();
+        let repeated = f.v1 == f.v2 || f.v2 == f.v3 || f.v3 == f.v1;
+        let in_range = f.v1 < n && f.v2 < n && f.v3 < n;
+        if repeated || (in_range && is_degenerate(mesh.vertices[f.v1], mesh.vertices[f.v2], mesh.vertices[f.v3])) {
+            report.degenerate_facets.push(i);
+        }
+    }
+    if !report.invalid_indices.is_empty() {
+        return report;
+    }
+
+    // Only the connectivity is needed, so the facets are enough.
+    let topo = match Topology::new(Mesh::new_from_parts(mesh.vertices.clone(), mesh.facets.clone())) {
+        Ok(t) => t,
+        Err(_) => return report,
+    };
+    report.non_manifold_edges = topo.non_manifold_edges();
+    report.non_manifold_vertices = topo.non_manifold_vertices();
+    report.boundary_edges = topo.boundary_edges();
+    report.holes = topo.boundary_loops();
+    for e in topo.edges().iter() {
+        if e.half_edges.len() == 2 {
+            let h = topo.half_edges()[e.half_edges[0]];
+            let g = topo.half_edges()[e.half_edges[1]];
+            if h.origin == g.origin && h.facet != g.facet {
+                report.inconsistent_facets.push((h.facet, g.facet));
+            }
+        }
+    }
+    report
+}
+
+// A repeated corner, or an area that is rounding noise next to the longest
+// edge, as in slivers whose corners are all but in a line.
+pub fn is_degenerate(a: Vector3D, b: Vector3D, c: Vector3D) -> bool {
+    if a == b || b == c || c == a {
+        return true;
+    }
+    let twice_area = Vector3D::cross(b.minus(a), c.minus(a)).length();
+    let longest = [b.minus(a), c.minus(b), a.minus(c)].iter()
+        .map(|e| Vector3D::dot(*e, *e))
+        .fold(0.0, |m: f32, l| m.max(l));
+    !(twice_area > f32::EPSILON * longest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Facet,Mesh};
+    use vector::Vector3D;
+
+    fn f(a: usize, b: usize, c: usize) -> Facet {
+        Facet { v1: a, v2: b, v3: c, n: Vector3D { x: 0.0, y: 0.0, z: 0.0 } }
+    }
+
+    fn tetrahedron() -> Mesh {
+        let v = |x: f32, y: f32, z: f32| Vector3D { x: x, y: y, z: z };
+        Mesh::new_from_parts(vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0), v(0.0, 0.0, 1.0)],
+                             vec![f(0, 2, 1), f(0, 1, 3), f(1, 2, 3), f(0, 3, 2)])
+    }
+
+    #[test]
+    fn closed_tetrahedron_is_valid() {
+        let report = validate(&tetrahedron());
+        assert!(report.is_valid(), "{:?}", report);
+        assert!(report.to_json().starts_with("{\"valid\":true,\"watertight\":true,\"vertices\":4,\"facets\":4,"));
+    }
+
+    #[test]
+    fn problems_are_reported() {
+        let mut flipped = tetrahedron();
+        flipped.facets[3] = f(0, 2, 3);
+        let report = validate(&flipped);
+        assert!(report.is_watertight() && !report.is_valid());
+        assert_eq!(report.inconsistent_facets, vec![(0, 3), (1, 3), (2, 3)]);
+
+        let mut mesh = tetrahedron();
+        mesh.facets.push(f(2, 1, 0));
+        mesh.facets.push(f(1, 1, 2));
+        let report = validate(&mesh);
+        assert_eq!(report.duplicate_facets, vec![4]);
+        assert_eq!(report.degenerate_facets, vec![5]);
+        assert!(report.to_json().contains("\"duplicate_facets\":[4]}"));
+
+        let mut open = tetrahedron();
+        open.facets.pop();
+        open.facets.push(f(0, 1, 7));
+        let report = validate(&open);
+        assert_eq!(report.invalid_indices, vec![(3, 7)]);
+        assert!(!report.is_watertight());
+
+        let mut open = tetrahedron();
+        open.facets.pop();
+        let report = validate(&open);
+        assert_eq!(report.boundary_edges.len(), 3);
+        assert_eq!(report.holes.len(), 1);
+        assert!(report.inconsistent_facets.is_empty());
+    }
+}
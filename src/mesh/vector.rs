@@ -8,9 +8,10 @@ use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::mem::{transmute};
 use std::num::Float;
-use std::old_io::{IoResult,Reader,Writer};
+use std::old_io::{Reader,Writer};
+use error::MeshResult;
 
-#[derive(PartialEq, PartialOrd, Copy)] //Show,
+#[derive(PartialEq, PartialOrd, Clone, Copy)] //Show,
 pub struct Vector3D {
     pub x: f32,
     pub y: f32,
@@ -31,14 +32,14 @@ impl fmt::Debug for Vector3D {
 }
 
 impl Vector3D {
-    pub fn read(r: &mut Reader) -> IoResult<Vector3D> {
+    pub fn read(r: &mut Reader) -> MeshResult<Vector3D> {
         let xr = try!(r.read_le_f32());
         let yr = try!(r.read_le_f32());
         let zr = try!(r.read_le_f32());
         return Ok(Vector3D { x: xr, y: yr, z: zr });
     }
 
-    pub fn write(&self, w: &mut Writer) -> IoResult<()> {
+    pub fn write(&self, w: &mut Writer) -> MeshResult<()> {
         try!(w.write_le_f32(self.x));
         try!(w.write_le_f32(self.y));
         try!(w.write_le_f32(self.z));
@@ -56,6 +57,30 @@ impl Vector3D {
         }
     }
 
+    pub fn plus(&self, o: Vector3D) -> Vector3D {
+        Vector3D {
+            x: self.x + o.x,
+            y: self.y + o.y,
+            z: self.z + o.z
+        }
+    }
+
+    pub fn scale(&self, s: f32) -> Vector3D {
+        Vector3D {
+            x: self.x * s,
+            y: self.y * s,
+            z: self.z * s
+        }
+    }
+
+    pub fn dot(a: Vector3D, b: Vector3D) -> f32 {
+        a.x * b.x + a.y * b.y + a.z * b.z
+    }
+
+    pub fn length(&self) -> f32 {
+        Vector3D::dot(*self, *self).sqrt()
+    }
+
     pub fn cross(a: Vector3D, b:Vector3D) -> Vector3D {
         let cx = a.y * b.z - a.z * b.y;
         let cy = a.z * b.x - a.x * b.z;
@@ -95,6 +120,17 @@ mod tests {
         assert_eq!(result.z, -3.0);
     }
 
+    #[test]
+    fn vectors_can_be_added_and_scaled() {
+        let l = Vector3D {x:1.0, y:1.0, z:1.0};
+        let r = Vector3D {x:2.0, y:3.0, z:4.0};
+        let result = l.plus(r).scale(2.0);
+        assert_eq!(result.x, 6.0);
+        assert_eq!(result.y, 8.0);
+        assert_eq!(result.z, 10.0);
+        assert_eq!(Vector3D::dot(l, r), 9.0);
+    }
+
     #[test]
     fn vectors_can_cross_product() {
         let l = Vector3D {x:3.0, y:-3.0, z:1.0};
@@ -115,32 +151,67 @@ mod tests {
         //assert_eq!(result.y.round(), 0.267);
         //assert_eq!(result.y.round(), 0.534);
     }
+
+    #[test]
+    fn vertex_map_keeps_insertion_order() {
+        let a = Vector3D {x:1.0, y:2.0, z:3.0};
+        let b = Vector3D {x:-1.0, y:0.0, z:0.5};
+        let c = Vector3D {x:0.0, y:0.0, z:0.0};
+        let mut vm = VertexMap::new();
+        assert_eq!(vm.add(b), 0);
+        assert_eq!(vm.add(a), 1);
+        assert_eq!(vm.add(b), 0);
+        assert_eq!(vm.add(c), 2);
+        assert_eq!(vm.len(), 3);
+        assert_eq!(vm.get(&a), Some(1));
+        assert_eq!(vm.vector(), vec![b, a, c]);
+        assert_eq!(vm.into_vector(), vec![b, a, c]);
+    }
+
+    #[test]
+    fn signed_zeros_are_one_vertex() {
+        let mut vm = VertexMap::new();
+        assert_eq!(vm.add(Vector3D {x:0.0, y:1.0, z:0.0}), 0);
+        assert_eq!(vm.add(Vector3D {x:-0.0, y:1.0, z:-0.0}), 0);
+        assert_eq!(vm.len(), 1);
+    }
 }
 
-// Implement Hash since there is no default for f32. We'll just hash the bits
-// since we know the f32s will all be canonical from reading.
+// Implement Hash since there is no default for f32. We hash the bits, with
+// -0.0 folded into 0.0 so that values that compare equal hash equally. This
+// makes VertexMap, and so STL indexing, treat the two zeros as one vertex,
+// as welding does.
 impl Hash for Vector3D {
     #[inline]
     fn hash<H: Hasher>(&self, state: &mut H) {
-        //println!("V3D hash {:p}", self);
-        let x: u32 = unsafe { transmute(self.x) };
-        x.hash(state);
-        let y: u32 = unsafe { transmute(self.y) };
-        y.hash(state);
-        let z: u32 = unsafe { transmute(self.z) };
-        z.hash(state);
-        //println!("V3D hash done {:p}", self);
+        canonical_bits(self.x).hash(state);
+        canonical_bits(self.y).hash(state);
+        canonical_bits(self.z).hash(state);
+    }
+}
+
+fn canonical_bits(v: f32) -> u32 {
+    if v == 0.0 {
+        0
+    } else {
+        unsafe { transmute(v) }
     }
 }
 
+// Gives each distinct vertex an index in the order it is first added. The
+// vertices live in a Vec in that order, so vector() just copies it out and
+// into_vector() hands it over, and the same sequence of adds always produces
+// the same indices and output.
 pub struct VertexMap {
-    vertices: HashMap<Vector3D,usize>,
+    vertices: Vec<Vector3D>,
+    indices: HashMap<Vector3D,usize>,
 }
 
 impl VertexMap {
     pub fn new() -> VertexMap {
         VertexMap {
-            vertices: HashMap::new(),
+            vertices: Vec::new(),
+            indices: HashMap::new(),
         }
     }
 
@@ -148,34 +219,31 @@ impl VertexMap {
         self.vertices.len()
     }
 
-    pub fn get(&self, v3d: &Vector3D) -> usize {
-        *self.vertices.get(v3d).unwrap()
+    pub fn get(&self, v3d: &Vector3D) -> Option<usize> {
+        self.indices.get(v3d).map(|idx| *idx)
     }
 
     pub fn add(&mut self, vertex: Vector3D) -> usize {
-        if self.vertices.contains_key(&vertex) {
-            //println!("add existing {}", vector3d_str(&vertex));
-            *self.vertices.get(&vertex).unwrap()
-        } else {
-            //println!("add new {}", vector3d_str(&vertex));
-            let idx = self.vertices.len();
-            self.vertices.insert(vertex, idx);
-            idx
+        if let Some(idx) = self.indices.get(&vertex) {
+            return *idx;
         }
+        let idx = self.vertices.len();
+        self.vertices.push(vertex);
+        self.indices.insert(vertex, idx);
+        idx
     }
 
     pub fn vector(&self) -> Vec<Vector3D> {
-        let mut v: Vec<Vector3D> = Vec::with_capacity(self.vertices.len());
-        for (v3d, _) in self.vertices.iter() {
-            v.push(*v3d);  // Funkiness just to get v expanded. Below writes the real values.
-        }
-        for (v3d, idx) in self.vertices.iter() {
-            //println!("v3d:{:?} idx:{}", v3d, idx);
-            v[*idx] = *v3d;
-        }
-        //for i in 0..v.len() {
-        //    println!("v3d[{}]: {:?}", i, v[i]);
-        //}
-        v
+        self.vertices.clone()
+    }
+
+    // The vertices, handed over without a copy.
+    pub fn into_vector(self) -> Vec<Vector3D> {
+        self.vertices
+    }
+
+    // The vertices so far, without copying them.
+    pub fn vertices(&self) -> &[Vector3D] {
+        &self.vertices
     }
 }
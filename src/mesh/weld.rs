@@ -0,0 +1,181 @@
+// Merging vertices that lie within a tolerance of each other, as CAD exports
+// often leave shared corners a few ulps apart.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::collections::HashMap;
+use std::num::Float;
+use mesh::Mesh;
+use vector::{Vector3D,VertexMap};
+use error::{MeshError,MeshResult};
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct WeldReport {
+    // Vertices folded into another one.
+    pub merged_vertices: usize,
+    // Facets left with fewer than three distinct corners.
+    pub dropped_facets: usize,
+}
+
+// Each vertex, in order, joins the nearest earlier kept vertex within
+// `epsilon`, or is kept itself. Kept vertices do not move, so merging is not
+// transitive: a chain of points each just under `epsilon` apart keeps every
+// other one. Facets are remapped, collapsed ones are dropped along with their
+// colours and attributes, and per-vertex data follows the kept vertices.
+// -0.0 and 0.0 are the same coordinate here, so an epsilon of 0 merges only
+// exact duplicates.
+pub fn weld(mesh: &mut Mesh, epsilon: f32) -> MeshResult<WeldReport> {
+    if !(epsilon >= 0.0) || epsilon.is_infinite() {
+        return Err(MeshError::InvalidArgument(format!("weld tolerance must be a finite number >= 0: {}", epsilon)));
+    }
+    try!(mesh.check_indices());
+
+    let mut grid = Grid::new(epsilon);
+    let mut kept: Vec<usize> = Vec::new();
+    let mut remap: Vec<usize> = Vec::with_capacity(mesh.vertices.len());
+    for (i, v) in mesh.vertices.iter().enumerate() {
+        let idx = grid.add(canonical(*v));
+        if idx == kept.len() {
+            kept.push(i);
+        }
+        remap.push(idx);
+    }
+    let merged = mesh.vertices.len() - kept.len();
+
+    mesh.vertices = grid.into_vertices();
+    if !mesh.vertex_normals.is_empty() {
+        mesh.vertex_normals = kept.iter().map(|&i| mesh.vertex_normals[i]).collect();
+    }
+    if !mesh.vertex_colors.is_empty() {
+        mesh.vertex_colors = kept.iter().map(|&i| mesh.vertex_colors[i]).collect();
+    }
+    for f in mesh.facets.iter_mut() {
+        f.v1 = remap[f.v1];
+        f.v2 = remap[f.v2];
+        f.v3 = remap[f.v3];
+    }
+    let dropped = mesh.retain_facets(|_, f| f.v1 != f.v2 && f.v2 != f.v3 && f.v3 != f.v1);
+
+    Ok(WeldReport { merged_vertices: merged, dropped_facets: dropped })
+}
+
+// Adding 0.0 turns -0.0 into 0.0 and leaves everything else alone. The
+// VertexMap matches the two zeros either way; this keeps -0.0 out of the
+// welded vertices.
+fn canonical(v: Vector3D) -> Vector3D {
+    Vector3D { x: v.x + 0.0, y: v.y + 0.0, z: v.z + 0.0 }
+}
+
+// Kept vertices bucketed by the epsilon sized cube they fall in, so a lookup
+// only has to look at the 27 cubes around a point.
+struct Grid {
+    epsilon: f32,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+    // Exact matches, and every vertex when epsilon is 0.
+    exact: VertexMap,
+}
+
+impl Grid {
+    fn new(epsilon: f32) -> Grid {
+        Grid { epsilon: epsilon, cells: HashMap::new(), exact: VertexMap::new() }
+    }
+
+    fn into_vertices(self) -> Vec<Vector3D> {
+        self.exact.into_vector()
+    }
+
+    // The index of the kept vertex `v` joins, which is a new one when
+    // nothing is close enough.
+    fn add(&mut self, v: Vector3D) -> usize {
+        if let Some(idx) = self.exact.get(&v) {
+            return idx;
+        }
+        let finite = v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        if self.epsilon == 0.0 || !finite {
+            return self.exact.add(v);
+        }
+        let cell = self.cell(v);
+        if let Some(idx) = self.nearest(v, cell) {
+            return idx;
+        }
+        let idx = self.exact.add(v);
+        self.cells.entry(cell).get().unwrap_or_else(|e| e.insert(Vec::new())).push(idx);
+        idx
+    }
+
+    fn cell(&self, v: Vector3D) -> (i64, i64, i64) {
+        ((v.x / self.epsilon).floor() as i64,
+         (v.y / self.epsilon).floor() as i64,
+         (v.z / self.epsilon).floor() as i64)
+    }
+
+    // Ties go to the earlier vertex, so the result does not depend on the
+    // order of the cells.
+    fn nearest(&self, v: Vector3D, cell: (i64, i64, i64)) -> Option<usize> {
+        let vertices = self.exact.vertices();
+        let limit = self.epsilon * self.epsilon;
+        let mut best: Option<(f32, usize)> = None;
+        for dx in -1..2 {
+            for dy in -1..2 {
+                for dz in -1..2 {
+                    let key = (cell.0 + dx, cell.1 + dy, cell.2 + dz);
+                    let candidates = match self.cells.get(&key) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    for &i in candidates.iter() {
+                        let d = v.minus(vertices[i]);
+                        let d2 = Vector3D::dot(d, d);
+                        let better = match best {
+                            None => true,
+                            Some((bd, bi)) => d2 < bd || (d2 == bd && i < bi),
+                        };
+                        if d2 <= limit && better {
+                            best = Some((d2, i));
+                        }
+                    }
+                }
+            }
+        }
+        best.map(|(_, i)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mesh::{Color,Facet,Mesh};
+    use vector::Vector3D;
+
+    fn v(x: f32, y: f32, z: f32) -> Vector3D {
+        Vector3D { x: x, y: y, z: z }
+    }
+
+    fn f(a: usize, b: usize, c: usize) -> Facet {
+        Facet { v1: a, v2: b, v3: c, n: v(0.0, 0.0, 1.0) }
+    }
+
+    #[test]
+    fn near_vertices_merge_and_collapsed_facets_go() {
+        let vertices = vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0),
+                            v(1.0, 1e-6, 0.0), v(1.0, 1.0, 0.0), v(-0.0, 1.0, -0.0),
+                            v(1.0, 0.0, 1e-6)];
+        let mut mesh = Mesh::new_from_parts(vertices, vec![f(0, 1, 2), f(3, 4, 5), f(1, 3, 6)]);
+        mesh.facet_colors = vec![Color::rgb(1.0, 0.0, 0.0), Color::rgb(0.0, 1.0, 0.0), Color::white()];
+        let report = weld(&mut mesh, 1e-4).unwrap();
+        assert_eq!(report, WeldReport { merged_vertices: 3, dropped_facets: 1 });
+        assert_eq!(mesh.vertices, vec![v(0.0, 0.0, 0.0), v(1.0, 0.0, 0.0), v(0.0, 1.0, 0.0), v(1.0, 1.0, 0.0)]);
+        assert_eq!(mesh.facets, vec![f(0, 1, 2), f(1, 3, 2)]);
+        assert_eq!(mesh.facet_colors, vec![Color::rgb(1.0, 0.0, 0.0), Color::rgb(0.0, 1.0, 0.0)]);
+    }
+
+    #[test]
+    fn zero_tolerance_merges_signed_zeros_only() {
+        let mut mesh = Mesh::new_from_parts(vec![v(0.0, 0.0, 0.0), v(-0.0, 0.0, 0.0), v(1e-9, 0.0, 0.0)],
+                                            vec![f(0, 1, 2)]);
+        let report = weld(&mut mesh, 0.0).unwrap();
+        assert_eq!(report, WeldReport { merged_vertices: 1, dropped_facets: 1 });
+        assert_eq!(mesh.vertices.len(), 2);
+        assert!(weld(&mut mesh, -1.0).is_err());
+    }
+}
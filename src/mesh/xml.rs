@@ -0,0 +1,306 @@
+// Minimal XML pull parser, just enough for the XML based mesh formats.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+
+use std::char;
+use parse::parse_error;
+use error::MeshResult;
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Event {
+    Start(String, Vec<(String, String)>),
+    End(String),
+    Text(String),
+}
+
+pub struct XmlReader<'a> {
+    src: &'a str,
+    pos: usize,
+    line: usize,
+    open: Vec<String>,
+    pending_end: Option<String>,
+}
+
+impl<'a> XmlReader<'a> {
+    pub fn new(src: &'a str) -> XmlReader<'a> {
+        XmlReader { src: src, pos: 0, line: 1, open: Vec::new(), pending_end: None }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    // Returns the next element start, element end or non-blank text run, or
+    // None once the document is exhausted. Declarations, comments and
+    // doctypes are skipped; self-closing elements produce a Start and an End.
+    pub fn next_event(&mut self) -> MeshResult<Option<Event>> {
+        if let Some(name) = self.pending_end.take() {
+            return Ok(Some(Event::End(name)));
+        }
+        loop {
+            if self.pos >= self.src.len() {
+                return match self.open.pop() {
+                    Some(name) => Err(parse_error(self.line, format!("unclosed element <{}>", name))),
+                    None => Ok(None),
+                };
+            }
+            if !self.rest().starts_with("<") {
+                let end = match self.rest().find('<') {
+                    Some(i) => self.pos + i,
+                    None => self.src.len(),
+                };
+                let raw = self.advance_to(end);
+                if raw.trim().is_empty() {
+                    continue;
+                }
+                let text = try!(self.unescape(raw));
+                return Ok(Some(Event::Text(text)));
+            }
+            if self.rest().starts_with("<!--") {
+                try!(self.skip_past("-->"));
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.pos += 9;
+                let start = self.pos;
+                try!(self.skip_past("]]>"));
+                let text = self.src[start..self.pos - 3].to_string();
+                return Ok(Some(Event::Text(text)));
+            } else if self.rest().starts_with("<?") {
+                try!(self.skip_past("?>"));
+            } else if self.rest().starts_with("<!") {
+                try!(self.skip_past(">"));
+            } else if self.rest().starts_with("</") {
+                self.pos += 2;
+                let name = self.read_name();
+                self.skip_space();
+                try!(self.skip_past(">"));
+                return match self.open.pop() {
+                    Some(ref open) if *open == name => Ok(Some(Event::End(name))),
+                    Some(open) => Err(parse_error(self.line,
+                        format!("expected </{}>, found </{}>", open, name))),
+                    None => Err(parse_error(self.line, format!("unexpected </{}>", name))),
+                };
+            } else {
+                return self.read_start().map(|e| Some(e));
+            }
+        }
+    }
+
+    // Like next_event, but running out of input is an error.
+    pub fn expect_event(&mut self) -> MeshResult<Event> {
+        match try!(self.next_event()) {
+            Some(e) => Ok(e),
+            None => Err(parse_error(self.line, "unexpected end of document".to_string())),
+        }
+    }
+
+    // Collects the text content of the element whose start was just read.
+    pub fn read_text(&mut self, name: &str) -> MeshResult<String> {
+        let mut text = String::new();
+        loop {
+            match try!(self.expect_event()) {
+                Event::Text(t) => text.push_str(&t),
+                Event::End(_) => return Ok(text),
+                Event::Start(child, _) => return Err(parse_error(self.line,
+                    format!("unexpected <{}> inside <{}>", child, name))),
+            }
+        }
+    }
+
+    pub fn read_f32(&mut self, name: &str) -> MeshResult<f32> {
+        let text = try!(self.read_text(name));
+        match text.trim().parse::<f32>() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(parse_error(self.line, format!("invalid number '{}' in <{}>", text.trim(), name))),
+        }
+    }
+
+    pub fn read_usize(&mut self, name: &str) -> MeshResult<usize> {
+        let text = try!(self.read_text(name));
+        match text.trim().parse::<usize>() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(parse_error(self.line, format!("invalid index '{}' in <{}>", text.trim(), name))),
+        }
+    }
+
+    // Skips the rest of the element whose start was just read.
+    pub fn skip_element(&mut self) -> MeshResult<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match try!(self.expect_event()) {
+                Event::Start(..) => depth += 1,
+                Event::End(_) => depth -= 1,
+                Event::Text(_) => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn read_start(&mut self) -> MeshResult<Event> {
+        self.pos += 1;
+        let name = self.read_name();
+        if name.is_empty() {
+            return Err(parse_error(self.line, "expected an element name after '<'".to_string()));
+        }
+        let mut attrs = Vec::new();
+        loop {
+            self.skip_space();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                self.pending_end = Some(name.clone());
+                return Ok(Event::Start(name, attrs));
+            }
+            if self.rest().starts_with(">") {
+                self.pos += 1;
+                self.open.push(name.clone());
+                return Ok(Event::Start(name, attrs));
+            }
+            let key = self.read_name();
+            if key.is_empty() {
+                return Err(parse_error(self.line, format!("malformed attribute in <{}>", name)));
+            }
+            self.skip_space();
+            if !self.rest().starts_with("=") {
+                return Err(parse_error(self.line, format!("expected '=' after attribute '{}'", key)));
+            }
+            self.pos += 1;
+            self.skip_space();
+            let quote = match self.rest().chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _ => return Err(parse_error(self.line, format!("attribute '{}' is not quoted", key))),
+            };
+            self.pos += 1;
+            let end = match self.rest().find(quote) {
+                Some(i) => self.pos + i,
+                None => return Err(parse_error(self.line, format!("unterminated attribute '{}'", key))),
+            };
+            let raw = self.advance_to(end);
+            self.pos += 1;
+            let value = try!(self.unescape(raw));
+            attrs.push((key, value));
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    // Moves to the byte offset `end`, counting newlines on the way.
+    fn advance_to(&mut self, end: usize) -> &'a str {
+        let s = &self.src[self.pos..end];
+        self.line += s.bytes().filter(|b| *b == b'\n').count();
+        self.pos = end;
+        s
+    }
+
+    fn skip_past(&mut self, marker: &str) -> MeshResult<()> {
+        match self.rest().find(marker) {
+            Some(i) => {
+                let end = self.pos + i + marker.len();
+                self.advance_to(end);
+                Ok(())
+            },
+            None => Err(parse_error(self.line, format!("missing '{}'", marker))),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let end = match self.rest().find(|c: char| !c.is_whitespace()) {
+            Some(i) => self.pos + i,
+            None => self.src.len(),
+        };
+        self.advance_to(end);
+    }
+
+    fn read_name(&mut self) -> String {
+        let end = match self.rest().find(|c: char| c.is_whitespace() || c == '/' || c == '>' || c == '=') {
+            Some(i) => self.pos + i,
+            None => self.src.len(),
+        };
+        self.advance_to(end).to_string()
+    }
+
+    fn unescape(&self, raw: &str) -> MeshResult<String> {
+        let mut out = String::with_capacity(raw.len());
+        let mut rest = raw;
+        while let Some(amp) = rest.find('&') {
+            out.push_str(&rest[..amp]);
+            let semi = match rest[amp..].find(';') {
+                Some(i) => amp + i,
+                None => return Err(parse_error(self.line, "unterminated entity".to_string())),
+            };
+            let entity = &rest[amp + 1..semi];
+            let c = match entity {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if entity.starts_with("#x") =>
+                    u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32),
+                _ if entity.starts_with("#") =>
+                    entity[1..].parse::<u32>().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            match c {
+                Some(c) => out.push(c),
+                None => return Err(parse_error(self.line, format!("unknown entity '&{};'", entity))),
+            }
+            rest = &rest[semi + 1..];
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+// Looks up an attribute value by name.
+pub fn attr<'b>(attrs: &'b [(String, String)], name: &str) -> Option<&'b str> {
+    attrs.iter().find(|a| a.0 == name).map(|a| &a.1[..])
+}
+
+// Escapes text for use in element content or a quoted attribute value.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::Event::*;
+    use parse::parse_error;
+
+    #[test]
+    fn reads_elements_attributes_and_text() {
+        let doc = "<?xml version='1.0'?>\n<!-- c -->\n<a x='1' y=\"&lt;2&gt;\">\n  <b/>hi &amp; bye</a>";
+        let mut xml = XmlReader::new(doc);
+        assert_eq!(xml.next_event().unwrap(),
+            Some(Start("a".to_string(), vec![("x".to_string(), "1".to_string()),
+                                             ("y".to_string(), "<2>".to_string())])));
+        assert_eq!(xml.next_event().unwrap(), Some(Start("b".to_string(), vec![])));
+        assert_eq!(xml.next_event().unwrap(), Some(End("b".to_string())));
+        assert_eq!(xml.line(), 4);
+        assert_eq!(xml.next_event().unwrap(), Some(Text("hi & bye".to_string())));
+        assert_eq!(xml.next_event().unwrap(), Some(End("a".to_string())));
+        assert_eq!(xml.next_event().unwrap(), None);
+    }
+
+    #[test]
+    fn mismatched_end_is_an_error() {
+        let mut xml = XmlReader::new("<a>\n<b></a>");
+        xml.next_event().unwrap();
+        xml.next_event().unwrap();
+        let err = xml.next_event().err().unwrap();
+        assert_eq!(err, parse_error(2, "expected </b>, found </a>".to_string()));
+    }
+}
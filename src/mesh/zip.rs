@@ -0,0 +1,242 @@
+// Just enough of the zip container format for AMF and 3MF packages: stored
+// and deflated entries, no zip64, encryption or multi-disk archives.
+#![allow(dead_code)]
+#![deny(unused_imports)]
+
+use std::old_io::Writer;
+use flate;
+use error::{MeshError,MeshResult};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+pub struct ZipEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && le32(data, 0) == Some(LOCAL_HEADER)
+}
+
+pub fn find<'a>(entries: &'a [ZipEntry], name: &str) -> Option<&'a ZipEntry> {
+    entries.iter().find(|e| e.name == name)
+}
+
+// A central directory record.
+struct DirEntry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    local: usize,
+}
+
+// Names of the files in the archive, without decompressing anything.
+pub fn names(data: &[u8]) -> MeshResult<Vec<String>> {
+    let directory = try!(read_directory(data));
+    Ok(directory.into_iter().map(|d| d.name).filter(|n| !n.ends_with("/")).collect())
+}
+
+// Reads every file in the archive, walking the central directory.
+pub fn read_entries(data: &[u8]) -> MeshResult<Vec<ZipEntry>> {
+    let directory = try!(read_directory(data));
+    let mut entries = Vec::with_capacity(directory.len());
+    for d in directory.into_iter() {
+        if d.name.ends_with("/") {
+            continue;
+        }
+        let local = d.local;
+        if try!(field32(data, local)) != LOCAL_HEADER {
+            return Err(zip_error(format!("bad local header for {}", d.name)));
+        }
+        let start = local + 30 + try!(field16(data, local + 26)) as usize
+                          + try!(field16(data, local + 28)) as usize;
+        let raw = try!(slice(data, start, d.compressed_size));
+        let contents = match d.method {
+            STORED => raw.to_vec(),
+            DEFLATED => match flate::inflate_bytes(raw) {
+                Some(bytes) => bytes[..].to_vec(),
+                None => return Err(zip_error(format!("corrupt deflate data in {}", d.name))),
+            },
+            _ => return Err(zip_error(format!("unsupported compression method {} in {}", d.method, d.name))),
+        };
+        if crc32(&contents) != d.crc {
+            return Err(zip_error(format!("checksum mismatch in {}", d.name)));
+        }
+        entries.push(ZipEntry { name: d.name, data: contents });
+    }
+    Ok(entries)
+}
+
+fn read_directory(data: &[u8]) -> MeshResult<Vec<DirEntry>> {
+    let eocd = match (0..data.len().saturating_sub(21)).rev()
+                    .find(|i| le32(data, *i) == Some(END_OF_DIRECTORY)) {
+        Some(i) => i,
+        None => return Err(zip_error("missing end of central directory".to_string())),
+    };
+    let count = try!(field16(data, eocd + 10));
+    let mut pos = try!(field32(data, eocd + 16)) as usize;
+    let mut directory = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        if try!(field32(data, pos)) != CENTRAL_HEADER {
+            return Err(zip_error(format!("bad central directory entry at {}", pos)));
+        }
+        let name_len = try!(field16(data, pos + 28)) as usize;
+        let extra_len = try!(field16(data, pos + 30)) as usize;
+        let comment_len = try!(field16(data, pos + 32)) as usize;
+        directory.push(DirEntry {
+            name: String::from_utf8_lossy(try!(slice(data, pos + 46, name_len))).into_owned(),
+            method: try!(field16(data, pos + 10)),
+            crc: try!(field32(data, pos + 16)),
+            compressed_size: try!(field32(data, pos + 20)) as usize,
+            local: try!(field32(data, pos + 42)) as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(directory)
+}
+
+pub fn write_entries(w: &mut Writer, entries: &[ZipEntry], compress: bool) -> MeshResult<()> {
+    let mut offset = 0u32;
+    let mut directory = Vec::new();
+    for e in entries.iter() {
+        let crc = crc32(&e.data);
+        let deflated = if compress { flate::deflate_bytes(&e.data).map(|b| b[..].to_vec()) } else { None };
+        let (method, body) = match deflated {
+            Some(ref d) if d.len() < e.data.len() => (DEFLATED, &d[..]),
+            _ => (STORED, &e.data[..]),
+        };
+        let name = e.name.as_bytes();
+
+        try!(w.write_le_u32(LOCAL_HEADER));
+        try!(write_common(w, method, crc, body.len(), e.data.len(), name.len()));
+        try!(w.write_le_u16(0));                    // extra field length
+        try!(w.write_all(name));
+        try!(w.write_all(body));
+
+        directory.push((method, crc, body.len(), e.data.len(), name, offset));
+        offset += (30 + name.len() + body.len()) as u32;
+    }
+
+    let directory_start = offset;
+    for &(method, crc, compressed, size, name, local) in directory.iter() {
+        try!(w.write_le_u32(CENTRAL_HEADER));
+        try!(w.write_le_u16(20));                   // made by
+        try!(write_common(w, method, crc, compressed, size, name.len()));
+        try!(w.write_le_u16(0));                    // extra field length
+        try!(w.write_le_u16(0));                    // comment length
+        try!(w.write_le_u16(0));                    // disk number
+        try!(w.write_le_u16(0));                    // internal attributes
+        try!(w.write_le_u32(0));                    // external attributes
+        try!(w.write_le_u32(local));
+        try!(w.write_all(name));
+        offset += (46 + name.len()) as u32;
+    }
+
+    try!(w.write_le_u32(END_OF_DIRECTORY));
+    try!(w.write_le_u16(0));                        // this disk
+    try!(w.write_le_u16(0));                        // directory disk
+    try!(w.write_le_u16(entries.len() as u16));
+    try!(w.write_le_u16(entries.len() as u16));
+    try!(w.write_le_u32(offset - directory_start));
+    try!(w.write_le_u32(directory_start));
+    try!(w.write_le_u16(0));                        // comment length
+    Ok(())
+}
+
+// The part shared by local and central headers, from "version needed"
+// through the file name length.
+fn write_common(w: &mut Writer, method: u16, crc: u32, compressed: usize, size: usize,
+                name_len: usize) -> MeshResult<()> {
+    try!(w.write_le_u16(20));                       // version needed
+    try!(w.write_le_u16(0));                        // flags
+    try!(w.write_le_u16(method));
+    try!(w.write_le_u16(0));                        // time
+    try!(w.write_le_u16(0x21));                     // date, 1980-01-01
+    try!(w.write_le_u32(crc));
+    try!(w.write_le_u32(compressed as u32));
+    try!(w.write_le_u32(size as u32));
+    try!(w.write_le_u16(name_len as u16));
+    Ok(())
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for b in data.iter() {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    if at + 4 > data.len() {
+        return None;
+    }
+    Some((data[at] as u32) | (data[at + 1] as u32) << 8 |
+         (data[at + 2] as u32) << 16 | (data[at + 3] as u32) << 24)
+}
+
+fn field32(data: &[u8], at: usize) -> MeshResult<u32> {
+    match le32(data, at) {
+        Some(v) => Ok(v),
+        None => Err(zip_error("archive is truncated".to_string())),
+    }
+}
+
+fn field16(data: &[u8], at: usize) -> MeshResult<u16> {
+    if at + 2 > data.len() {
+        return Err(zip_error("archive is truncated".to_string()));
+    }
+    Ok((data[at] as u16) | (data[at + 1] as u16) << 8)
+}
+
+fn slice(data: &[u8], at: usize, len: usize) -> MeshResult<&[u8]> {
+    if at + len > data.len() {
+        return Err(zip_error("archive is truncated".to_string()));
+    }
+    Ok(&data[at..at + len])
+}
+
+// Archives have no lines to point at.
+fn zip_error(msg: String) -> MeshError {
+    MeshError::Parse { line: 0, msg: msg }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::old_io::MemWriter;
+
+    #[test]
+    fn crc_matches_reference() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let text: Vec<u8> = "<amf></amf>\n".bytes().cycle().take(4000).collect();
+        let entries = vec![
+            ZipEntry { name: "a.amf".to_string(), data: text.clone() },
+            ZipEntry { name: "b.txt".to_string(), data: b"tiny".to_vec() },
+        ];
+        let mut w = MemWriter::new();
+        write_entries(&mut w, &entries, true).unwrap();
+        let data = w.into_inner();
+        assert!(is_zip(&data));
+        assert!(data.len() < text.len());
+
+        assert_eq!(names(&data).unwrap(), vec!["a.amf".to_string(), "b.txt".to_string()]);
+        let read = read_entries(&data).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(find(&read, "a.amf").unwrap().data, text);
+        assert_eq!(find(&read, "b.txt").unwrap().data, b"tiny".to_vec());
+    }
+}